@@ -0,0 +1,543 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{info, warn, Instrument};
+
+use crate::config::ClientConfig;
+use crate::metrics::{Metrics, NoopMetrics};
+use crate::xapi_definitions::commands_common::{Cmd, State, Type};
+use crate::xapi_definitions::commands_main::*;
+use crate::xapi_definitions::commands_stream::*;
+use crate::session::XApiSession;
+use crate::{ConnectionClosed, StreamConnection, XApiClient};
+
+/// Events buffered for `AccountManager::next_event`. When the caller falls behind,
+/// newer events are dropped, see `AccountManager::dropped_events`.
+pub const EVENT_BUFFER: usize = 4096;
+
+/// Stream message received on one of the managed accounts.
+#[derive(Debug, Clone)]
+pub struct AccountEvent {
+    pub account: String,
+    pub event: ResponseStream,
+}
+
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub account: String,
+    pub position: u32,
+    pub order: u32,
+    pub symbol: String,
+    pub cmd: Cmd,
+    pub r#type: Type,
+    pub volume: f32,
+    pub open_price: f32,
+    pub sl: f32,
+    pub tp: f32,
+    pub profit: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccountBalance {
+    pub account: String,
+    pub currency: String,
+    pub balance: f32,
+    pub credit: f32,
+    pub equity: f32,
+    pub margin: f32,
+    pub margin_free: f32,
+}
+
+/// Sums of `AccountBalance` over the accounts sharing a currency.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceTotals {
+    pub accounts: usize,
+    pub balance: f32,
+    pub credit: f32,
+    pub equity: f32,
+    pub margin: f32,
+    pub margin_free: f32,
+}
+
+/// Latest balance and open positions, seeded on login and kept current from the stream.
+#[derive(Debug)]
+struct AccountState {
+    balance: AccountBalance,
+    positions: BTreeMap<u32, Position>,
+}
+
+impl AccountState {
+    fn observe(&mut self, event: &ResponseStream) {
+        match event {
+            ResponseStream::Balance(balance) => {
+                let balance = &balance.data;
+                self.balance.balance = balance.balance;
+                self.balance.credit = balance.credit;
+                self.balance.equity = balance.equity;
+                self.balance.margin = balance.margin;
+                self.balance.margin_free = balance.margin_free;
+            }
+            ResponseStream::Trade(trade) => {
+                let trade = &trade.data;
+                if trade.closed || trade.state == State::Deleted {
+                    self.positions.remove(&trade.position);
+                } else if matches!(trade.r#type, Type::Open | Type::Pending) {
+                    let profit = self.positions.get(&trade.position).map(|position| position.profit);
+                    self.positions.insert(trade.position, Position {
+                        account: self.balance.account.clone(),
+                        position: trade.position,
+                        order: trade.order,
+                        symbol: trade.symbol.clone(),
+                        cmd: trade.cmd,
+                        r#type: trade.r#type,
+                        volume: trade.volume,
+                        open_price: trade.open_price,
+                        sl: trade.sl,
+                        tp: trade.tp,
+                        profit: trade.profit.or(profit).unwrap_or_default(),
+                    });
+                }
+            }
+            ResponseStream::Profit(profit) => {
+                if let Some(position) = self.positions.get_mut(&profit.data.position) {
+                    position.profit = profit.data.profit;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+struct Account {
+    config: ClientConfig,
+    client: XApiClient,
+    state: Arc<Mutex<AccountState>>,
+    stream_task: JoinHandle<()>,
+}
+
+impl Drop for Account {
+    fn drop(&mut self) {
+        self.stream_task.abort();
+    }
+}
+
+/// Logged-in accounts keyed by user id. Each has its own main connection for
+/// requests and a stream connection read on a background task; the streams
+/// are merged into one feed of `AccountEvent`s.
+pub struct AccountManager {
+    accounts: BTreeMap<String, Account>,
+    events: mpsc::Receiver<AccountEvent>,
+    sender: mpsc::Sender<AccountEvent>,
+    dropped: Arc<AtomicU64>,
+    metrics: Arc<dyn Metrics>,
+    log_payloads: bool,
+}
+
+impl Default for AccountManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccountManager {
+    pub fn new() -> Self {
+        let (sender, events) = mpsc::channel(EVENT_BUFFER);
+        Self {
+            accounts: BTreeMap::new(),
+            events,
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+            metrics: Arc::new(NoopMetrics),
+            log_payloads: false,
+        }
+    }
+
+    /// Applied to the connections of accounts added afterwards.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn log_payloads(mut self, log_payloads: bool) -> Self {
+        self.log_payloads = log_payloads;
+        self
+    }
+
+    /// Logs in, loads balance and open trades, then subscribes to balance, trades,
+    /// profits, trade statuses and ticks of `config.symbols`. Returns the account id.
+    pub async fn add(&mut self, config: ClientConfig, login_request: Request) -> Result<String, Box<dyn Error>> {
+        let account = self.account_id(&login_request)?;
+        let span = account_span(&account, &config);
+        let connected = connect(account, config, login_request, self.metrics.clone(), self.log_payloads)
+            .instrument(span)
+            .await?;
+        Ok(self.insert(connected))
+    }
+
+    /// Adds several accounts, logging them in concurrently. Accounts that fail are
+    /// returned with their error, the others are added.
+    pub async fn add_all(
+        &mut self,
+        logins: Vec<(ClientConfig, Request)>,
+    ) -> Vec<(String, Result<(), Box<dyn Error>>)> {
+        let mut results = Vec::new();
+        let mut tasks = JoinSet::new();
+        for (config, login_request) in logins {
+            let account = match self.account_id(&login_request) {
+                Ok(account) => account,
+                Err(err) => {
+                    let account = match login_request {
+                        Request::Login(login) => login.user_id,
+                        _ => config.profile,
+                    };
+                    results.push((account, Err(err)));
+                    continue;
+                }
+            };
+            let metrics = self.metrics.clone();
+            let log_payloads = self.log_payloads;
+            let span = account_span(&account, &config);
+            tasks.spawn(async move {
+                connect(account.clone(), config, login_request, metrics, log_payloads).await
+                    .map_err(|err| (account, err.to_string()))
+            }.instrument(span));
+        }
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(Ok(connected)) if self.accounts.contains_key(&connected.account) => {
+                    let error = format!("Account {} is already managed", connected.account);
+                    results.push((connected.account, Err(error.into())));
+                }
+                Ok(Ok(connected)) => {
+                    let account = self.insert(connected);
+                    results.push((account, Ok(())));
+                }
+                Ok(Err((account, err))) => results.push((account, Err(err.into()))),
+                Err(err) => results.push((String::new(), Err(err.into()))),
+            }
+        }
+        results
+    }
+
+    fn account_id(&self, login_request: &Request) -> Result<String, Box<dyn Error>> {
+        let Request::Login(login) = login_request else {
+            return Err("Expected a login request".into());
+        };
+        if self.accounts.contains_key(&login.user_id) {
+            Err(format!("Account {} is already managed", login.user_id))?;
+        }
+        Ok(login.user_id.clone())
+    }
+
+    fn insert(&mut self, connected: Connected) -> String {
+        let Connected { account, config, client, client_stream, state } = connected;
+        let span = account_span(&account, &config);
+        let state = Arc::new(Mutex::new(state));
+        let stream_task = tokio::spawn(
+            read_stream(account.clone(), client_stream, state.clone(), self.sender.clone(), self.dropped.clone()).instrument(span),
+        );
+        self.accounts.insert(account.clone(), Account { config, client, state, stream_task });
+        account
+    }
+
+    /// Drops both connections of the account.
+    pub fn remove(&mut self, account: &str) -> Result<(), Box<dyn Error>> {
+        self.accounts.remove(account)
+            .map(|_| ())
+            .ok_or_else(|| format!("Account {} is not managed", account).into())
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &str> {
+        self.accounts.keys().map(String::as_str)
+    }
+
+    pub fn config(&self, account: &str) -> Option<&ClientConfig> {
+        self.accounts.get(account).map(|account| &account.config)
+    }
+
+    /// Main connection of the account, for requests such as trade transactions.
+    pub fn client(&mut self, account: &str) -> Option<&mut XApiClient> {
+        self.accounts.get_mut(account).map(|account| &mut account.client)
+    }
+
+    /// Whether the stream task of the account is still reading.
+    pub fn is_streaming(&self, account: &str) -> bool {
+        self.accounts.get(account).is_some_and(|account| !account.stream_task.is_finished())
+    }
+
+    /// Next stream message of any account. The account state is already updated
+    /// with it when it is returned.
+    pub async fn next_event(&mut self) -> Option<AccountEvent> {
+        self.events.recv().await
+    }
+
+    /// Events dropped because `EVENT_BUFFER` events were waiting for `next_event`.
+    /// Balances and positions still include them, only the event itself is lost.
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn balance(&self, account: &str) -> Option<AccountBalance> {
+        self.accounts.get(account).map(|account| account.state.lock().unwrap().balance.clone())
+    }
+
+    pub fn balances(&self) -> Vec<AccountBalance> {
+        self.accounts.values()
+            .map(|account| account.state.lock().unwrap().balance.clone())
+            .collect()
+    }
+
+    /// Balances summed per account currency.
+    pub fn total_balance(&self) -> BTreeMap<String, BalanceTotals> {
+        let mut totals: BTreeMap<String, BalanceTotals> = BTreeMap::new();
+        for balance in self.balances() {
+            let total = totals.entry(balance.currency).or_default();
+            total.accounts += 1;
+            total.balance += balance.balance;
+            total.credit += balance.credit;
+            total.equity += balance.equity;
+            total.margin += balance.margin;
+            total.margin_free += balance.margin_free;
+        }
+        totals
+    }
+
+    pub fn positions(&self, account: &str) -> Vec<Position> {
+        self.accounts.get(account)
+            .map(|account| account.state.lock().unwrap().positions.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Open positions and pending orders of every account.
+    pub fn all_positions(&self) -> Vec<Position> {
+        self.accounts.values()
+            .flat_map(|account| account.state.lock().unwrap().positions.values().cloned().collect::<Vec<_>>())
+            .collect()
+    }
+
+    /// Net open volume per symbol across accounts, sells counted negative.
+    pub fn net_volume(&self) -> BTreeMap<String, f32> {
+        let mut volumes = BTreeMap::new();
+        for position in self.all_positions() {
+            let volume = match position.cmd {
+                Cmd::Buy => position.volume,
+                Cmd::Sell => -position.volume,
+                _ => continue,
+            };
+            *volumes.entry(position.symbol).or_default() += volume;
+        }
+        volumes
+    }
+}
+
+async fn read_stream(
+    account: String,
+    mut client_stream: StreamConnection,
+    state: Arc<Mutex<AccountState>>,
+    sender: mpsc::Sender<AccountEvent>,
+    dropped: Arc<AtomicU64>,
+) {
+    loop {
+        match client_stream.response_stream().await {
+            Ok(event) => {
+                state.lock().unwrap().observe(&event);
+                // Never waits on the consumer, a stalled stream read would get the connection dropped.
+                match sender.try_send(AccountEvent { account: account.clone(), event }) {
+                    Ok(()) => {}
+                    Err(mpsc::error::TrySendError::Full(_)) => {
+                        if dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                            warn!("event buffer full, dropping events");
+                        }
+                    }
+                    Err(mpsc::error::TrySendError::Closed(_)) => return,
+                }
+            }
+            Err(err) if err.is::<ConnectionClosed>() => {
+                warn!("stream closed");
+                return;
+            }
+            Err(err) => warn!(error = %err, "stream message skipped"),
+        }
+    }
+}
+
+/// Both connections of a freshly logged-in account, before its stream task starts.
+struct Connected {
+    account: String,
+    config: ClientConfig,
+    client: XApiClient,
+    client_stream: StreamConnection,
+    state: AccountState,
+}
+
+fn account_span(account: &str, config: &ClientConfig) -> tracing::Span {
+    tracing::info_span!("account", account = %account, profile = %config.profile)
+}
+
+async fn connect(
+    account: String,
+    config: ClientConfig,
+    login_request: Request,
+    metrics: Arc<dyn Metrics>,
+    log_payloads: bool,
+) -> Result<Connected, Box<dyn Error>> {
+    let mut session = XApiSession::connect_with_metrics(&config, &login_request, metrics).await?;
+    session.log_payloads(log_payloads);
+
+    let client = session.client();
+    client.execute_command(&Request::GetMarginLevel(GetMarginLevel {})).await?;
+    let margin_level = client.response_data::<GetMarginLevelResponse>().await?.return_data;
+    client.execute_command(&Request::GetTrades(GetTradesRequest { opened_only: true })).await?;
+    let trades = client.response_data::<Vec<TradeRecord>>().await?.return_data;
+
+    let positions = trades.into_iter()
+        .map(|trade| (trade.position, Position {
+            account: account.clone(),
+            position: trade.position,
+            order: trade.order,
+            symbol: trade.symbol.unwrap_or_default(),
+            cmd: trade.cmd,
+            r#type: if matches!(trade.cmd, Cmd::Buy | Cmd::Sell) { Type::Open } else { Type::Pending },
+            volume: trade.volume,
+            open_price: trade.open_price,
+            sl: trade.sl,
+            tp: trade.tp,
+            profit: trade.profit.unwrap_or_default(),
+        }))
+        .collect();
+    let state = AccountState {
+        balance: AccountBalance {
+            account: account.clone(),
+            currency: margin_level.currency,
+            balance: margin_level.balance,
+            credit: margin_level.credit,
+            equity: margin_level.equity,
+            margin: margin_level.margin,
+            margin_free: margin_level.margin_free,
+        },
+        positions,
+    };
+
+    session.subscribe_keep_alive().await?;
+    session.subscribe_balance().await?;
+    session.subscribe_trades().await?;
+    session.subscribe_profits().await?;
+    session.subscribe_trade_status().await?;
+    for symbol in &config.symbols {
+        session.subscribe_tick_prices(symbol, Some(1), Some(0)).await?;
+    }
+
+    info!("account ready");
+    let (client, client_stream) = session.into_parts();
+    Ok(Connected { account, config, client, client_stream, state })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xapi_definitions::commands_stream::GetResponse;
+
+    fn state() -> AccountState {
+        AccountState {
+            balance: AccountBalance {
+                account: "1234".into(),
+                currency: "EUR".into(),
+                balance: 1000.0,
+                credit: 0.0,
+                equity: 1000.0,
+                margin: 0.0,
+                margin_free: 1000.0,
+            },
+            positions: BTreeMap::new(),
+        }
+    }
+
+    fn trade(position: u32, cmd: Cmd, r#type: Type, state: State, profit: Option<f32>) -> ResponseStream {
+        ResponseStream::Trade(GetResponse {
+            data: GetTradesReponse {
+                close_price: 0.0,
+                close_time: None,
+                closed: false,
+                cmd,
+                comment: String::new(),
+                commission: 0.0,
+                custom_comment: None,
+                digits: 5,
+                expiration: None,
+                margin_rate: 0.0,
+                offset: 0,
+                open_price: 1.1,
+                open_time: 0,
+                order: position,
+                order2: position,
+                position,
+                profit,
+                sl: 0.0,
+                state,
+                storage: 0.0,
+                symbol: "EURUSD".into(),
+                tp: 0.0,
+                r#type,
+                volume: 0.1,
+                extra: Default::default(),
+            },
+            extra: Default::default(),
+        })
+    }
+
+    fn profit(position: u32, profit: f32) -> ResponseStream {
+        ResponseStream::Profit(GetResponse {
+            data: GetProfitsResponse { order: position, order2: position, position, profit, extra: Default::default() },
+            extra: Default::default(),
+        })
+    }
+
+    #[test]
+    fn open_and_pending_trades_are_inserted() {
+        let mut state = state();
+        state.observe(&trade(1, Cmd::Buy, Type::Open, State::Modified, Some(2.0)));
+        state.observe(&trade(2, Cmd::SellLimit, Type::Pending, State::Modified, None));
+        state.observe(&trade(3, Cmd::Buy, Type::Modify, State::Modified, None));
+
+        assert_eq!(state.positions.keys().copied().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(state.positions[&1].account, "1234");
+        assert_eq!(state.positions[&1].profit, 2.0);
+        assert_eq!(state.positions[&2].r#type, Type::Pending);
+    }
+
+    #[test]
+    fn closed_and_deleted_trades_are_removed() {
+        let mut state = state();
+        state.observe(&trade(1, Cmd::Buy, Type::Open, State::Modified, None));
+        state.observe(&trade(2, Cmd::SellLimit, Type::Pending, State::Modified, None));
+
+        if let ResponseStream::Trade(mut closed) = trade(1, Cmd::Buy, Type::Close, State::Modified, Some(5.0)) {
+            closed.data.closed = true;
+            state.observe(&ResponseStream::Trade(closed));
+        }
+        state.observe(&trade(2, Cmd::SellLimit, Type::Pending, State::Deleted, None));
+        assert!(state.positions.is_empty());
+    }
+
+    #[test]
+    fn profit_is_kept_when_a_trade_update_has_none() {
+        let mut state = state();
+        state.observe(&trade(1, Cmd::Buy, Type::Open, State::Modified, None));
+        state.observe(&profit(1, 7.5));
+        assert_eq!(state.positions[&1].profit, 7.5);
+
+        state.observe(&trade(1, Cmd::Buy, Type::Open, State::Modified, None));
+        assert_eq!(state.positions[&1].profit, 7.5);
+        state.observe(&trade(1, Cmd::Buy, Type::Open, State::Modified, Some(-1.0)));
+        assert_eq!(state.positions[&1].profit, -1.0);
+
+        // Profits of unknown positions are ignored.
+        state.observe(&profit(9, 1.0));
+        assert_eq!(state.positions.len(), 1);
+    }
+}
@@ -0,0 +1,247 @@
+use std::error::Error;
+
+use serde::Serialize;
+
+use crate::history::Candle;
+use crate::sim::*;
+use crate::strategy::{dispatch, Orders, Strategy};
+use crate::timestamp_to_datetime;
+use crate::xapi_definitions::commands_stream::*;
+
+/// Rounds of orders a strategy may submit in reaction to the events of its own orders,
+/// within one quote or candle, before the backtest is stopped.
+pub const MAX_ORDER_ROUNDS: usize = 100;
+
+/// Ask minus bid for a simulated quote.
+pub trait SpreadModel {
+    fn spread(&mut self, symbol: &str, price: f64) -> f64;
+}
+
+pub struct FixedSpread(pub f64);
+
+impl SpreadModel for FixedSpread {
+    fn spread(&mut self, _symbol: &str, _price: f64) -> f64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EquityPoint {
+    pub ctm: i64,
+    pub balance: f64,
+    pub equity: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacktestReport {
+    pub trades: Vec<ClosedTrade>,
+    pub open_positions: Vec<SimOrder>,
+    pub equity_curve: Vec<EquityPoint>,
+    pub initial_balance: f64,
+    pub final_balance: f64,
+}
+
+impl BacktestReport {
+    pub fn net_profit(&self) -> f64 {
+        self.final_balance - self.initial_balance
+    }
+
+    pub fn max_drawdown(&self) -> f64 {
+        let mut peak = f64::MIN;
+        let mut max_drawdown = 0.0;
+        for point in &self.equity_curve {
+            peak = peak.max(point.equity);
+            max_drawdown = f64::max(max_drawdown, peak - point.equity);
+        }
+        max_drawdown
+    }
+}
+
+/// Replays stored candles through a `Strategy` in simulated time.
+pub struct Backtester {
+    symbol: String,
+    initial_balance: f64,
+    account: SimulatedAccount,
+    spread: Box<dyn SpreadModel>,
+}
+
+impl Backtester {
+    pub fn new(symbol: &str, initial_balance: f64) -> Self {
+        Self {
+            symbol: symbol.into(),
+            initial_balance,
+            account: SimulatedAccount::new(initial_balance),
+            spread: Box::new(FixedSpread(0.0)),
+        }
+    }
+
+    pub fn spread<M: SpreadModel + 'static>(mut self, spread: M) -> Self {
+        self.spread = Box::new(spread);
+        self
+    }
+
+    pub fn slippage<M: SlippageModel + 'static>(mut self, slippage: M) -> Self {
+        self.account = self.account.slippage(slippage);
+        self
+    }
+
+    pub fn commission<M: CommissionModel + 'static>(mut self, commission: M) -> Self {
+        self.account = self.account.commission(commission);
+        self
+    }
+
+    pub fn contract_size(mut self, contract_size: f64) -> Self {
+        self.account = self.account.contract_size(contract_size);
+        self
+    }
+
+    pub fn leverage(mut self, leverage: f64) -> Self {
+        self.account = self.account.leverage(leverage);
+        self
+    }
+
+    fn deliver<S: Strategy + ?Sized>(&mut self, strategy: &mut S, events: Vec<ResponseStream>, orders: &mut Orders, time: i64) -> Result<(), Box<dyn Error>> {
+        let mut events = events;
+        for _ in 0..=MAX_ORDER_ROUNDS {
+            if events.is_empty() {
+                return Ok(());
+            }
+            for event in &events {
+                dispatch(strategy, event, orders);
+            }
+            events = orders.drain().iter()
+                .flat_map(|order| self.account.submit(order, time).events)
+                .collect();
+        }
+        Err(format!(
+            "Strategy kept submitting orders in reaction to its own orders at {}, stopped after {} rounds",
+            timestamp_to_datetime(time), MAX_ORDER_ROUNDS,
+        ))?
+    }
+
+    fn tick(&self, bid: f64, ask: f64, candle: &Candle, time: i64) -> GetTickPricesResponse {
+        GetTickPricesResponse {
+            ask: ask as f32,
+            ask_volume: 0,
+            bid: bid as f32,
+            bid_volume: 0,
+            high: candle.high as f32,
+            level: 0,
+            low: candle.low as f32,
+            quote_id: 0,
+            spread_raw: (ask - bid) as f32,
+            spread_table: (ask - bid) as f32,
+            symbol: self.symbol.clone(),
+            timestamp: time,
+            extra: Default::default(),
+        }
+    }
+
+    fn candle(&self, candle: &Candle) -> GetCandlesResponse {
+        GetCandlesResponse {
+            close: candle.close as f32,
+            ctm: candle.ctm,
+            ctm_string: timestamp_to_datetime(candle.ctm),
+            high: candle.high as f32,
+            low: candle.low as f32,
+            open: candle.open as f32,
+            quote_id: 0,
+            symbol: self.symbol.clone(),
+            vol: candle.vol as f32,
+            extra: Default::default(),
+        }
+    }
+
+    /// Walks each candle open -> low -> high -> close (open -> high -> low -> close for
+    /// bearish candles), so SL/TP and pending orders are triggered inside the bar.
+    fn price_path(candle: &Candle) -> [f64; 4] {
+        if candle.close >= candle.open {
+            [candle.open, candle.low, candle.high, candle.close]
+        } else {
+            [candle.open, candle.high, candle.low, candle.close]
+        }
+    }
+
+    pub fn run<S: Strategy + ?Sized>(mut self, strategy: &mut S, candles: &[Candle]) -> Result<BacktestReport, Box<dyn Error>> {
+        let mut orders = Orders::default();
+        let mut equity_curve = Vec::with_capacity(candles.len());
+
+        for (index, candle) in candles.iter().enumerate() {
+            let bar_length = candles.get(index + 1).map(|next| next.ctm - candle.ctm).unwrap_or(0);
+            for (step, price) in Self::price_path(candle).into_iter().enumerate() {
+                let time = candle.ctm + bar_length * step as i64 / 4;
+                let ask = price + self.spread.spread(&self.symbol, price);
+                let quote = Quote { symbol: self.symbol.clone(), bid: price, ask, time };
+
+                let mut events = self.account.on_quote(quote);
+                events.push(ResponseStream::TickPrices(GetResponse { data: self.tick(price, ask, candle, time), extra: Default::default() }));
+                self.deliver(strategy, events, &mut orders, time)?;
+            }
+
+            let events = vec![ResponseStream::Candle(GetResponse { data: self.candle(candle), extra: Default::default() })];
+            self.deliver(strategy, events, &mut orders, candle.ctm + bar_length)?;
+
+            equity_curve.push(EquityPoint {
+                ctm: candle.ctm,
+                balance: self.account.balance(),
+                equity: self.account.equity(),
+            });
+        }
+
+        Ok(BacktestReport {
+            trades: self.account.closed().to_vec(),
+            open_positions: self.account.positions().to_vec(),
+            equity_curve,
+            initial_balance: self.initial_balance,
+            final_balance: self.account.balance(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::Orders;
+
+    fn candles() -> Vec<Candle> {
+        (0..3).map(|i| Candle { ctm: i * 60_000, open: 1.0, high: 1.1, low: 0.9, close: 1.05, vol: 1.0 }).collect()
+    }
+
+    /// Buys on every trade status, including the ones of its own buys.
+    struct Echo;
+
+    impl Strategy for Echo {
+        fn on_candle(&mut self, candle: &GetCandlesResponse, orders: &mut Orders) {
+            orders.buy(&candle.symbol, 0.1);
+        }
+
+        fn on_trade_status(&mut self, _status: &GetTradeStatusResponse, orders: &mut Orders) {
+            orders.buy("EURUSD", 0.1);
+        }
+    }
+
+    /// Buys once on the first candle.
+    struct Once(bool);
+
+    impl Strategy for Once {
+        fn on_candle(&mut self, candle: &GetCandlesResponse, orders: &mut Orders) {
+            if !self.0 {
+                self.0 = true;
+                orders.buy(&candle.symbol, 0.1);
+            }
+        }
+    }
+
+    #[test]
+    fn orders_reacting_to_own_orders_stop_the_backtest() {
+        let err = Backtester::new("EURUSD", 10_000.0).run(&mut Echo, &candles()).unwrap_err();
+        assert!(err.to_string().contains("rounds"), "{}", err);
+    }
+
+    #[test]
+    fn orders_are_executed() {
+        let report = Backtester::new("EURUSD", 10_000.0).run(&mut Once(false), &candles()).unwrap();
+        assert_eq!(report.open_positions.len(), 1);
+        assert_eq!(report.equity_curve.len(), 3);
+    }
+}
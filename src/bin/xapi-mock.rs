@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+
+use xtb::mock::{self, EngineConfig, Fixtures, MockConfig, MockServer, PriceSource};
+
+#[derive(Parser)]
+#[command(name = "xapi-mock", about = "Local mock of the xAPI main and stream servers")]
+struct Cli {
+    /// Address of the main protocol
+    #[arg(long, default_value = "127.0.0.1:5124")]
+    main_addr: String,
+
+    /// Address of the stream protocol
+    #[arg(long, default_value = "127.0.0.1:5125")]
+    stream_addr: String,
+
+    /// Plain TCP instead of TLS with a self-signed certificate
+    #[arg(long)]
+    plain: bool,
+
+    /// Accepted login as `user_id:password`, repeatable
+    #[arg(long = "user", value_parser = parse_user, required = true)]
+    users: Vec<(String, String)>,
+
+    /// JSON file with symbols, commission, current user data and trade status
+    #[arg(long)]
+    fixtures: Option<PathBuf>,
+
+    /// JSON array of `{"delay_ms": .., "message": ..}` stream messages
+    #[arg(long)]
+    script: Option<PathBuf>,
+
+    /// Seconds between keep-alive messages, 0 disables them
+    #[arg(long, default_value_t = 3)]
+    keep_alive: u64,
+
+    /// Simulate accounts, prices and order matching instead of fixed trade replies
+    #[arg(long)]
+    engine: bool,
+
+    /// Starting balance of simulated accounts
+    #[arg(long, default_value_t = 10_000.0)]
+    balance: f64,
+
+    /// Replay quotes from this file (JSON lines) instead of a random walk
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Milliseconds between simulated quotes
+    #[arg(long, default_value_t = 500)]
+    tick_ms: u64,
+
+    /// Largest relative price move per random walk step
+    #[arg(long, default_value_t = 0.0001)]
+    walk_step: f64,
+
+    /// Seed of the random walk
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// JSON array of faults to inject, e.g. `[{"after_ms": 5000, "channel": "main", "fault": "delay", "ms": 2000}]`
+    #[arg(long)]
+    faults: Option<PathBuf>,
+
+    /// Write the self-signed certificate as PEM to this file
+    #[arg(long)]
+    cert_out: Option<PathBuf>,
+
+    /// Log filter such as `info` or `xtb=debug`, `RUST_LOG` takes precedence
+    #[arg(long, default_value = "info")]
+    log_level: String,
+}
+
+fn parse_user(value: &str) -> Result<(String, String), String> {
+    value.split_once(':')
+        .map(|(user_id, password)| (user_id.to_string(), password.to_string()))
+        .ok_or_else(|| format!("Expected user_id:password, got {}", value))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&cli.log_level))?;
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut config = MockConfig {
+        tls: !cli.plain,
+        keep_alive: (cli.keep_alive > 0).then(|| Duration::from_secs(cli.keep_alive)),
+        ..MockConfig::default()
+    };
+    for (user_id, password) in &cli.users {
+        config = config.user(user_id, password);
+    }
+    if let Some(path) = &cli.fixtures {
+        config.fixtures = Fixtures::load(path)?;
+    }
+    if let Some(path) = &cli.script {
+        config.script = mock::load_script(path)?;
+    }
+    if let Some(path) = &cli.faults {
+        config.faults = mock::load_faults(path)?;
+    }
+    if cli.engine {
+        let interval = Duration::from_millis(cli.tick_ms);
+        let prices = match &cli.replay {
+            Some(path) => PriceSource::Replay { quotes: mock::load_quotes(path)?, interval },
+            None => PriceSource::RandomWalk { step: cli.walk_step, interval, seed: cli.seed },
+        };
+        config = config.engine(EngineConfig { balance: cli.balance, prices });
+    }
+
+    let handle = MockServer::new(config).bind(&cli.main_addr, &cli.stream_addr).await?;
+    if let (Some(path), Some(pem)) = (&cli.cert_out, handle.certificate_pem()) {
+        fs::write(path, pem)?;
+    }
+    println!("main {} stream {}", handle.main_addr(), handle.stream_addr());
+
+    tokio::select! {
+        _ = handle.wait() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
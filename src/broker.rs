@@ -0,0 +1,285 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+
+use crate::sim::{Quote, SimulatedAccount};
+use crate::strategy::{dispatch, Orders, Strategy};
+use crate::xapi_definitions::commands_common::RequestStatus;
+use crate::xapi_definitions::commands_main::*;
+use crate::xapi_definitions::commands_stream::*;
+use crate::session::XApiSession;
+use crate::{StreamConnection, XApiClient};
+
+use tracing::warn;
+
+/// Order entry and event feed shared by live and paper trading, so a strategy
+/// switches between them by constructing a different broker.
+#[allow(async_fn_in_trait)]
+pub trait Broker {
+    /// Sends a trade request and returns its order number.
+    async fn trade_transaction(&mut self, trade_trans_info: TradeTransInfo) -> Result<u32, Box<dyn Error>>;
+    async fn trade_transaction_status(&mut self, order: u32) -> Result<TradeTransactionStatusResponse, Box<dyn Error>>;
+    async fn next_event(&mut self) -> Result<ResponseStream, Box<dyn Error>>;
+}
+
+/// Feeds broker events to a strategy and executes the orders it queues.
+pub async fn run<B: Broker, S: Strategy + ?Sized>(broker: &mut B, strategy: &mut S) -> Result<(), Box<dyn Error>> {
+    let mut orders = Orders::default();
+    loop {
+        let event = broker.next_event().await?;
+        dispatch(strategy, &event, &mut orders);
+        for order in orders.drain() {
+            if let Err(err) = broker.trade_transaction(order).await {
+                warn!(error = %err, "trade transaction failed");
+            }
+        }
+    }
+}
+
+pub struct LiveBroker {
+    client: XApiClient,
+    stream: StreamConnection,
+}
+
+impl LiveBroker {
+    /// `client` must be logged in and `stream` subscribed to the required streams.
+    pub fn new(client: XApiClient, stream: StreamConnection) -> Self {
+        Self { client, stream }
+    }
+
+    /// Subscribe the session to the required streams first.
+    pub fn from_session(session: XApiSession) -> Self {
+        let (client, stream) = session.into_parts();
+        Self::new(client, stream)
+    }
+}
+
+impl Broker for LiveBroker {
+    async fn trade_transaction(&mut self, trade_trans_info: TradeTransInfo) -> Result<u32, Box<dyn Error>> {
+        let request = Request::TradeTransaction(TradeTransaction { trade_trans_info });
+        self.client.execute_command(&request).await?;
+        let response = self.client.response_data::<TradeTransactionResponse>().await?;
+        Ok(response.return_data.order)
+    }
+
+    async fn trade_transaction_status(&mut self, order: u32) -> Result<TradeTransactionStatusResponse, Box<dyn Error>> {
+        let request = Request::TradeTransactionStatus(TradeTransactionStatus { order });
+        self.client.execute_command(&request).await?;
+        let response = self.client.response_data::<TradeTransactionStatusResponse>().await?;
+        Ok(response.return_data)
+    }
+
+    async fn next_event(&mut self) -> Result<ResponseStream, Box<dyn Error>> {
+        self.stream.response_stream().await
+    }
+}
+
+/// Fills orders locally against live `getTickPrices` quotes and emits the
+/// trade, trade status and balance messages the server would send. Only quotes,
+/// candles and keep-alives are passed on from the live stream.
+pub struct PaperBroker {
+    stream: StreamConnection,
+    account: SimulatedAccount,
+    events: VecDeque<ResponseStream>,
+    statuses: HashMap<u32, TradeTransactionStatusResponse>,
+}
+
+impl PaperBroker {
+    /// `stream` must be subscribed to `getTickPrices` for every traded symbol.
+    pub fn new(stream: StreamConnection, initial_balance: f64) -> Self {
+        Self::with_account(stream, SimulatedAccount::new(initial_balance))
+    }
+
+    pub fn with_account(stream: StreamConnection, account: SimulatedAccount) -> Self {
+        Self {
+            stream,
+            account,
+            events: VecDeque::new(),
+            statuses: HashMap::new(),
+        }
+    }
+
+    pub fn account(&self) -> &SimulatedAccount {
+        &self.account
+    }
+
+    /// Applies a quote, queueing any fills, SL/TP or expirations it triggers.
+    pub fn on_tick(&mut self, tick: &GetTickPricesResponse) {
+        let events = self.account.on_quote(Quote::from_tick(tick));
+        self.record(&events, &tick.symbol);
+        self.events.extend(events);
+    }
+
+    fn record(&mut self, events: &[ResponseStream], symbol: &str) {
+        for event in events {
+            if let ResponseStream::TradeStatus(status) = event {
+                let quote = self.account.quote(symbol);
+                self.statuses.insert(status.data.order, TradeTransactionStatusResponse {
+                    ask: quote.map(|quote| quote.ask as f32).unwrap_or_default(),
+                    bid: quote.map(|quote| quote.bid as f32).unwrap_or_default(),
+                    custom_comment: status.data.custom_comment.clone(),
+                    message: status.data.message.clone(),
+                    order: status.data.order,
+                    request_status: status.data.request_status,
+                    extra: Default::default(),
+                });
+            }
+        }
+    }
+}
+
+impl Broker for PaperBroker {
+    async fn trade_transaction(&mut self, trade_trans_info: TradeTransInfo) -> Result<u32, Box<dyn Error>> {
+        let time = self.account.quote(&trade_trans_info.symbol).map(|quote| quote.time).unwrap_or_default();
+        let execution = self.account.submit(&trade_trans_info, time);
+        self.record(&execution.events, &trade_trans_info.symbol);
+        self.events.extend(execution.events);
+
+        match execution.request_status {
+            RequestStatus::Rejected | RequestStatus::Error => {
+                let error = format!("Trade transaction rejected: {}", execution.message.unwrap_or_default());
+                Err(error)?
+            }
+            _ => Ok(execution.order),
+        }
+    }
+
+    async fn trade_transaction_status(&mut self, order: u32) -> Result<TradeTransactionStatusResponse, Box<dyn Error>> {
+        match self.statuses.get(&order) {
+            Some(status) => Ok(status.clone()),
+            None => Err(format!("Order {} not found", order))?,
+        }
+    }
+
+    async fn next_event(&mut self) -> Result<ResponseStream, Box<dyn Error>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            // Account events of the live login are dropped, the simulated account sends its own.
+            let event = self.stream.response_stream().await?;
+            match &event {
+                ResponseStream::TickPrices(tick) => self.on_tick(&tick.data),
+                ResponseStream::Candle(_) | ResponseStream::KeepAlive(_) => {}
+                _ => continue,
+            }
+            self.events.push_back(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::NoopMetrics;
+    use crate::secret::Secret;
+    use crate::xapi_definitions::commands_common::{Cmd, Type};
+    use crate::Connection;
+
+    use std::sync::Arc;
+    use serde_json::json;
+    use tokio::io::{AsyncWriteExt, DuplexStream};
+
+    fn event(message: serde_json::Value) -> String {
+        serde_json::from_value::<ResponseStream>(message.clone()).unwrap();
+        message.to_string()
+    }
+
+    fn tick(bid: f32, ask: f32) -> String {
+        event(json!({"command": "tickPrices", "data": {
+            "ask": ask, "askVolume": 1000, "bid": bid, "bidVolume": 1000, "high": ask, "level": 0, "low": bid,
+            "quoteId": 1, "spreadRaw": ask - bid, "spreadTable": 2.0, "symbol": "EURUSD", "timestamp": 1_700_000_000_000i64,
+        }}))
+    }
+
+    /// Broker whose live stream sends `frames`, the returned peer keeps it open.
+    async fn broker(frames: &[String]) -> (PaperBroker, DuplexStream) {
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        for frame in frames {
+            server.write_all(frame.as_bytes()).await.unwrap();
+            server.write_all(b"\n\n").await.unwrap();
+        }
+        let stream = StreamConnection {
+            connection: Connection::over(Box::new(client), "paper".into(), Arc::new(NoopMetrics), false),
+            stream_session_id: Secret::new("paper"),
+        };
+        (PaperBroker::new(stream, 10_000.0), server)
+    }
+
+    fn buy(volume: f32) -> TradeTransInfo {
+        TradeTransInfo {
+            cmd: Cmd::Buy,
+            custom_comment: None,
+            expiration: 0,
+            offset: 0,
+            order: 0,
+            price: 0.0,
+            sl: 0.0,
+            symbol: "EURUSD".into(),
+            tp: 0.0,
+            r#type: Type::Open,
+            volume,
+        }
+    }
+
+    #[tokio::test]
+    async fn fills_come_from_the_simulated_account() {
+        let (mut broker, _peer) = broker(&[tick(1.1, 1.1002)]).await;
+        assert!(matches!(broker.next_event().await.unwrap(), ResponseStream::TickPrices(_)));
+
+        let order = broker.trade_transaction(buy(0.1)).await.unwrap();
+        match broker.next_event().await.unwrap() {
+            ResponseStream::TradeStatus(status) => {
+                assert_eq!(status.data.order, order);
+                assert_eq!(status.data.request_status, RequestStatus::Accepted);
+            }
+            event => panic!("expected a trade status, got {:?}", event),
+        }
+        match broker.next_event().await.unwrap() {
+            ResponseStream::Trade(trade) => {
+                assert_eq!(trade.data.order, order);
+                assert_eq!(trade.data.open_price, 1.1002);
+            }
+            event => panic!("expected a trade, got {:?}", event),
+        }
+        match broker.next_event().await.unwrap() {
+            ResponseStream::Balance(balance) => assert!(balance.data.balance <= 10_000.0),
+            event => panic!("expected a balance, got {:?}", event),
+        }
+
+        let status = broker.trade_transaction_status(order).await.unwrap();
+        assert_eq!(status.request_status, RequestStatus::Accepted);
+        assert_eq!(status.ask, 1.1002);
+        assert_eq!(broker.account().positions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_orders_report_a_rejected_status() {
+        let (mut broker, _peer) = broker(&[tick(1.1, 1.1002)]).await;
+        broker.next_event().await.unwrap();
+
+        assert!(broker.trade_transaction(buy(0.0)).await.is_err());
+        match broker.next_event().await.unwrap() {
+            ResponseStream::TradeStatus(status) => assert_eq!(status.data.request_status, RequestStatus::Rejected),
+            event => panic!("expected a trade status, got {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn account_events_of_the_live_login_are_not_forwarded() {
+        let (mut broker, _peer) = broker(&[
+            event(json!({"command": "balance", "data": {
+                "balance": 1.0, "credit": 0.0, "equity": 1.0, "margin": 0.0, "marginFree": 1.0, "marginLevel": 0.0,
+            }})),
+            event(json!({"command": "profit", "data": {"order": 7, "order2": 7, "position": 7, "profit": 1.0}})),
+            event(json!({"command": "tradeStatus", "data": {
+                "customComment": null, "message": null, "order": 7, "price": 1.0, "requestStatus": 3,
+            }})),
+            event(json!({"command": "keepAlive", "data": {"timestamp": 1}})),
+            tick(1.1, 1.1002),
+        ]).await;
+
+        assert!(matches!(broker.next_event().await.unwrap(), ResponseStream::KeepAlive(_)));
+        assert!(matches!(broker.next_event().await.unwrap(), ResponseStream::TickPrices(_)));
+        assert!(broker.trade_transaction_status(7).await.is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::transport::{ConnectOptions, Proxy};
+use crate::xapi_definitions::commands_main::{LoginRequest, Request};
+use crate::{Connected, Disconnected, XApiClient};
+
+pub const XAPI_HOST: &str = "xapi.xtb.com";
+pub const DEFAULT_APP_ID: &str = "test";
+pub const DEFAULT_APP_NAME: &str = "XTB_test";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    Real,
+    #[default]
+    Demo,
+    Custom,
+}
+
+impl Environment {
+    /// Main and stream ports of the preset, `None` for `Custom`.
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        match self {
+            Environment::Real => Some((5124, 5125)),
+            Environment::Demo => Some((5112, 5113)),
+            Environment::Custom => None,
+        }
+    }
+}
+
+impl FromStr for Environment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "real" => Ok(Environment::Real),
+            "demo" => Ok(Environment::Demo),
+            "custom" => Ok(Environment::Custom),
+            _ => Err(format!("Unknown environment {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Profile {
+    pub environment: Environment,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub stream_port: Option<u16>,
+    pub user_id: Option<String>,
+    pub app_id: Option<String>,
+    pub app_name: Option<String>,
+    pub symbols: Vec<String>,
+    /// Refuse trade transactions on sessions of this profile.
+    pub read_only: bool,
+    /// Plain TCP instead of TLS, only for local test servers.
+    pub plaintext: bool,
+    /// `socks5://[user:password@]host:port` or `http://[user:password@]host:port`.
+    pub proxy: Option<String>,
+}
+
+/// Layout of the TOML config file:
+///
+/// ```toml
+/// default_profile = "demo"
+///
+/// [profiles.demo]
+/// environment = "demo"
+/// user_id = "1234567"
+/// symbols = ["EURUSD", "US500"]
+///
+/// [profiles.monitoring]
+/// environment = "real"
+/// read_only = true
+/// proxy = "socks5://proxy.internal:1080"
+///
+/// [profiles.local]
+/// environment = "custom"
+/// host = "localhost"
+/// port = 5124
+/// stream_port = 5125
+/// plaintext = true
+/// ```
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl ConfigFile {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path.as_ref())
+            .map_err(|err| format!("Failed to read config {}: {}", path.as_ref().display(), err))?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// `$XAPI_CONFIG`, otherwise `xtb/config.toml` in the user config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = env::var("XAPI_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        env::var("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|_| env::var("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|_| env::var("APPDATA").map(PathBuf::from))
+            .ok()
+            .map(|dir| dir.join("xtb").join("config.toml"))
+    }
+}
+
+fn is_true(value: &str) -> bool {
+    matches!(value.to_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+/// Connection and login settings shared by the library and the CLI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub profile: String,
+    pub environment: Environment,
+    pub host: String,
+    pub port: u16,
+    pub stream_port: u16,
+    pub user_id: Option<String>,
+    pub app_id: String,
+    pub app_name: String,
+    pub symbols: Vec<String>,
+    pub read_only: bool,
+    pub plaintext: bool,
+    pub proxy: Option<Proxy>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self::preset(Environment::Demo)
+    }
+}
+
+impl ClientConfig {
+    pub fn preset(environment: Environment) -> Self {
+        let (port, stream_port) = environment.ports().unwrap_or((0, 0));
+        Self {
+            profile: "default".into(),
+            environment,
+            host: XAPI_HOST.into(),
+            port,
+            stream_port,
+            user_id: None,
+            app_id: DEFAULT_APP_ID.into(),
+            app_name: DEFAULT_APP_NAME.into(),
+            symbols: Vec::new(),
+            read_only: false,
+            plaintext: false,
+            proxy: None,
+        }
+    }
+
+    pub fn real() -> Self {
+        Self::preset(Environment::Real)
+    }
+
+    pub fn demo() -> Self {
+        Self::preset(Environment::Demo)
+    }
+
+    pub fn from_profile(name: &str, profile: &Profile) -> Result<Self, Box<dyn Error>> {
+        let (port, stream_port) = match (profile.environment.ports(), profile.port, profile.stream_port) {
+            (_, Some(port), Some(stream_port)) => (port, stream_port),
+            (Some((port, stream_port)), main, stream) => (main.unwrap_or(port), stream.unwrap_or(stream_port)),
+            (None, _, _) => Err(format!("Profile {} uses a custom environment and needs port and stream_port", name))?,
+        };
+        let host = match (profile.environment, &profile.host) {
+            (_, Some(host)) => host.clone(),
+            (Environment::Custom, None) => Err(format!("Profile {} uses a custom environment and needs host", name))?,
+            _ => XAPI_HOST.into(),
+        };
+
+        Ok(Self {
+            profile: name.into(),
+            environment: profile.environment,
+            host,
+            port,
+            stream_port,
+            user_id: profile.user_id.clone(),
+            app_id: profile.app_id.clone().unwrap_or_else(|| DEFAULT_APP_ID.into()),
+            app_name: profile.app_name.clone().unwrap_or_else(|| DEFAULT_APP_NAME.into()),
+            symbols: profile.symbols.clone(),
+            read_only: profile.read_only,
+            plaintext: profile.plaintext,
+            proxy: profile.proxy.as_deref().map(Proxy::from_url).transpose()?,
+        })
+    }
+
+    /// Loads a profile from `path` (or the default config path) and applies environment
+    /// overrides. A missing default config falls back to the demo preset.
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let profile = profile.map(String::from).or_else(|| env::var("XAPI_PROFILE").ok());
+        let file = match path {
+            Some(path) => Some(ConfigFile::read(path)?),
+            None => match ConfigFile::default_path() {
+                Some(path) if path.exists() => Some(ConfigFile::read(path)?),
+                _ => None,
+            },
+        };
+
+        let mut config = match file {
+            Some(file) => {
+                let name = profile.or(file.default_profile.clone())
+                    .or_else(|| (file.profiles.len() == 1).then(|| file.profiles.keys().next().cloned()).flatten());
+                match name {
+                    Some(name) => {
+                        let selected = file.profiles.get(&name)
+                            .ok_or_else(|| format!("Profile {} not found in config", name))?;
+                        Self::from_profile(&name, selected)?
+                    }
+                    None => Self::default(),
+                }
+            }
+            None if profile.is_some() => Err("Profile selected but no config file found")?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Overrides settings from `XAPI_ENV`, `XAPI_ADDRESS`, `XAPI_PORT`, `XAPI_PORT_STREAM`,
+    /// `XAPI_USER_ID`, `XAPI_APP_ID`, `XAPI_APP_NAME`, `XAPI_SYMBOLS` (comma separated) and
+    /// `XAPI_READ_ONLY` and `XAPI_PLAINTEXT` (`1` or `true`) and `XAPI_PROXY` (proxy URL).
+    pub fn apply_env(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Ok(environment) = env::var("XAPI_ENV") {
+            let environment: Environment = environment.parse()?;
+            if let Some((port, stream_port)) = environment.ports() {
+                self.port = port;
+                self.stream_port = stream_port;
+            }
+            self.environment = environment;
+        }
+        if let Ok(host) = env::var("XAPI_ADDRESS") {
+            self.host = host;
+        }
+        if let Ok(port) = env::var("XAPI_PORT") {
+            self.port = port.parse()?;
+        }
+        if let Ok(stream_port) = env::var("XAPI_PORT_STREAM") {
+            self.stream_port = stream_port.parse()?;
+        }
+        if let Ok(user_id) = env::var("XAPI_USER_ID") {
+            self.user_id = Some(user_id);
+        }
+        if let Ok(app_id) = env::var("XAPI_APP_ID") {
+            self.app_id = app_id;
+        }
+        if let Ok(app_name) = env::var("XAPI_APP_NAME") {
+            self.app_name = app_name;
+        }
+        if let Ok(symbols) = env::var("XAPI_SYMBOLS") {
+            self.symbols = symbols.split(',').map(|symbol| symbol.trim().to_string())
+                .filter(|symbol| !symbol.is_empty())
+                .collect();
+        }
+        if let Ok(read_only) = env::var("XAPI_READ_ONLY") {
+            self.read_only = is_true(&read_only);
+        }
+        if let Ok(plaintext) = env::var("XAPI_PLAINTEXT") {
+            self.plaintext = is_true(&plaintext);
+        }
+        if let Ok(proxy) = env::var("XAPI_PROXY") {
+            self.proxy = Some(Proxy::from_url(&proxy)?);
+        }
+        Ok(())
+    }
+
+    pub fn login_request(&self, user_id: &str, password: &str) -> Request {
+        Request::Login(
+            LoginRequest {
+                user_id: user_id.into(),
+                password: password.into(),
+                app_id: self.app_id.clone(),
+                app_name: self.app_name.clone(),
+            }
+        )
+    }
+
+    /// `plaintext` and `proxy` of the profile, to extend before passing to
+    /// `XApiClient::connect_options`.
+    pub fn connect_options(&self) -> ConnectOptions {
+        let options = ConnectOptions::new().plaintext(self.plaintext);
+        match &self.proxy {
+            Some(proxy) => options.proxy(proxy.clone()),
+            None => options,
+        }
+    }
+
+    /// Main connection client, not yet connected. Read-only when the profile is.
+    pub fn client(&self) -> XApiClient<Disconnected> {
+        let client = XApiClient::new(&self.host, &self.port.to_string())
+            .connect_options(self.connect_options());
+        if self.read_only { client.read_only() } else { client }
+    }
+
+    pub async fn connect(&self) -> Result<XApiClient<Connected>, Box<dyn Error>> {
+        self.client().connect().await
+    }
+}
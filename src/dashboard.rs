@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use chrono::Local;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Clear, List, ListItem, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use xtb::history::HistoryDownloader;
+use xtb::ConnectionClosed;
+use xtb::xapi_definitions::commands_common::*;
+use xtb::xapi_definitions::commands_main::*;
+use xtb::xapi_definitions::commands_stream::*;
+
+use crate::{get_trades, get_symbol, Session, DAY_MS};
+
+const LOG_SIZE: usize = 200;
+/// How often the input thread checks whether the dashboard was closed.
+const INPUT_POLL: Duration = Duration::from_millis(100);
+
+struct Quote {
+    bid: f32,
+    ask: f32,
+    /// Open of the current D1 candle, or the first bid seen when history is unavailable.
+    day_open: Option<f32>,
+}
+
+struct Position {
+    order: u32,
+    symbol: String,
+    cmd: Cmd,
+    volume: f32,
+    open_price: f32,
+    sl: f32,
+    tp: f32,
+    profit: f32,
+}
+
+impl Position {
+    fn from_trade_record(trade: TradeRecord) -> Self {
+        Self {
+            order: trade.order,
+            symbol: trade.symbol.unwrap_or_default(),
+            cmd: trade.cmd,
+            volume: trade.volume,
+            open_price: trade.open_price,
+            sl: trade.sl,
+            tp: trade.tp,
+            profit: trade.profit.unwrap_or_default(),
+        }
+    }
+
+    fn is_market(&self) -> bool {
+        matches!(self.cmd, Cmd::Buy | Cmd::Sell)
+    }
+}
+
+#[derive(Default)]
+struct Dashboard {
+    watchlist: BTreeMap<String, Quote>,
+    /// Keyed by position number.
+    positions: BTreeMap<u32, Position>,
+    balance: Option<GetBalanceResponse>,
+    log: VecDeque<String>,
+    selected: TableState,
+    /// Position waiting for the user to confirm the close.
+    confirm: Option<u32>,
+}
+
+impl Dashboard {
+    fn push_log(&mut self, line: String) {
+        if self.log.len() == LOG_SIZE {
+            self.log.pop_front();
+        }
+        self.log.push_back(format!("{} {}", Local::now().format("%H:%M:%S"), line));
+    }
+
+    fn selected_position(&self) -> Option<u32> {
+        self.selected.selected().and_then(|index| self.positions.keys().nth(index).copied())
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        let len = self.positions.len();
+        if len == 0 {
+            self.selected.select(None);
+            return;
+        }
+        let index = match (self.selected.selected(), down) {
+            (None, _) => 0,
+            (Some(index), true) => (index + 1).min(len - 1),
+            (Some(index), false) => index.saturating_sub(1),
+        };
+        self.selected.select(Some(index));
+    }
+
+    fn on_event(&mut self, event: ResponseStream) {
+        match event {
+            ResponseStream::TickPrices(tick) => {
+                let tick = tick.data;
+                let quote = self.watchlist.entry(tick.symbol).or_insert(Quote { bid: tick.bid, ask: tick.ask, day_open: None });
+                quote.bid = tick.bid;
+                quote.ask = tick.ask;
+                quote.day_open.get_or_insert(tick.bid);
+            }
+            ResponseStream::Trade(trade) => {
+                let trade = trade.data;
+                if trade.closed || trade.state == State::Deleted {
+                    if self.positions.remove(&trade.position).is_some() {
+                        self.push_log(format!("Position {} {} closed, profit {}",
+                            trade.position, trade.symbol, trade.profit.unwrap_or_default()));
+                    }
+                } else if matches!(trade.r#type, Type::Open | Type::Pending) {
+                    let profit = self.positions.get(&trade.position).map(|position| position.profit);
+                    self.positions.insert(trade.position, Position {
+                        order: trade.order,
+                        symbol: trade.symbol,
+                        cmd: trade.cmd,
+                        volume: trade.volume,
+                        open_price: trade.open_price,
+                        sl: trade.sl,
+                        tp: trade.tp,
+                        profit: trade.profit.or(profit).unwrap_or_default(),
+                    });
+                }
+                self.move_selection(false);
+            }
+            ResponseStream::Profit(profit) => {
+                if let Some(position) = self.positions.get_mut(&profit.data.position) {
+                    position.profit = profit.data.profit;
+                }
+            }
+            ResponseStream::Balance(balance) => {
+                self.balance = Some(balance.data);
+            }
+            ResponseStream::TradeStatus(status) => {
+                let status = status.data;
+                self.push_log(format!("Order {} {:?} at {} {}",
+                    status.order, status.request_status, status.price, status.message.unwrap_or_default()));
+            }
+            ResponseStream::Unknown(message) => {
+                self.push_log(format!("Unknown stream message {}", message["command"]));
+            }
+            ResponseStream::Candle(_) | ResponseStream::KeepAlive(_) => {}
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [top, positions, log, help] = Layout::vertical([
+            Constraint::Length(self.watchlist.len().max(3) as u16 + 3),
+            Constraint::Min(6),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+        let [watchlist, account] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(top);
+
+        self.draw_watchlist(frame, watchlist);
+        self.draw_account(frame, account);
+        self.draw_positions(frame, positions);
+        self.draw_log(frame, log);
+        frame.render_widget(
+            Paragraph::new("q quit  ↑/↓ select  c close position").style(Style::default().fg(Color::DarkGray)),
+            help,
+        );
+
+        if let Some(position) = self.confirm {
+            self.draw_confirm(frame, position);
+        }
+    }
+
+    fn draw_watchlist(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.watchlist.iter().map(|(symbol, quote)| {
+            let change = quote.day_open.map(|open| quote.bid - open).unwrap_or_default();
+            let percent = quote.day_open.filter(|open| *open != 0.0).map(|open| change / open * 100.0).unwrap_or_default();
+            let color = if change > 0.0 { Color::Green } else if change < 0.0 { Color::Red } else { Color::Reset };
+            Row::new(vec![
+                Cell::from(symbol.as_str()),
+                Cell::from(quote.bid.to_string()),
+                Cell::from(quote.ask.to_string()),
+                Cell::from(format!("{:.5}", quote.ask - quote.bid)),
+                Cell::from(format!("{:+.5} ({:+.2}%)", change, percent)).style(Style::default().fg(color)),
+            ])
+        });
+        let table = Table::new(rows, [
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(10),
+            Constraint::Min(18),
+        ])
+        .header(Row::new(["Symbol", "Bid", "Ask", "Spread", "Change"]).style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::bordered().title(" Watchlist "));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_account(&self, frame: &mut Frame, area: Rect) {
+        let lines = match &self.balance {
+            Some(balance) => vec![
+                Line::from(format!("Balance      {:>14.2}", balance.balance)),
+                Line::from(format!("Equity       {:>14.2}", balance.equity)),
+                Line::from(format!("Margin       {:>14.2}", balance.margin)),
+                Line::from(format!("Free margin  {:>14.2}", balance.margin_free)),
+                Line::from(format!("Margin level {:>13.2}%", balance.margin_level)),
+                Line::from(format!("Credit       {:>14.2}", balance.credit)),
+            ],
+            None => vec![Line::from("Waiting for balance...")],
+        };
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Account ")), area);
+    }
+
+    fn draw_positions(&mut self, frame: &mut Frame, area: Rect) {
+        let rows = self.positions.iter().map(|(position_id, position)| {
+            let current = self.watchlist.get(&position.symbol)
+                .map(|quote| if position.cmd == Cmd::Buy { quote.bid } else { quote.ask })
+                .filter(|_| position.is_market())
+                .map(|price| price.to_string())
+                .unwrap_or_default();
+            let color = if position.profit >= 0.0 { Color::Green } else { Color::Red };
+            Row::new(vec![
+                Cell::from(position_id.to_string()),
+                Cell::from(position.symbol.as_str()),
+                Cell::from(format!("{:?}", position.cmd)),
+                Cell::from(position.volume.to_string()),
+                Cell::from(position.open_price.to_string()),
+                Cell::from(current),
+                Cell::from(position.sl.to_string()),
+                Cell::from(position.tp.to_string()),
+                Cell::from(format!("{:.2}", position.profit)).style(Style::default().fg(color)),
+            ])
+        });
+        let table = Table::new(rows, [
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(12),
+            Constraint::Length(12),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Min(10),
+        ])
+        .header(Row::new(["Position", "Symbol", "Cmd", "Volume", "Open", "Current", "SL", "TP", "Profit"])
+            .style(Style::default().add_modifier(Modifier::BOLD)))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title(" Positions "));
+        frame.render_stateful_widget(table, area, &mut self.selected);
+    }
+
+    fn draw_log(&self, frame: &mut Frame, area: Rect) {
+        let visible = area.height.saturating_sub(2) as usize;
+        let items: Vec<ListItem> = self.log.iter()
+            .skip(self.log.len().saturating_sub(visible))
+            .map(|line| ListItem::new(line.as_str()))
+            .collect();
+        frame.render_widget(List::new(items).block(Block::bordered().title(" Trade status ")), area);
+    }
+
+    fn draw_confirm(&self, frame: &mut Frame, position_id: u32) {
+        let Some(position) = self.positions.get(&position_id) else {
+            return;
+        };
+        let action = if position.is_market() { "Close" } else { "Delete" };
+        let [area] = Layout::horizontal([Constraint::Length(50)]).flex(Flex::Center).areas(frame.area());
+        let [area] = Layout::vertical([Constraint::Length(4)]).flex(Flex::Center).areas(area);
+        frame.render_widget(Clear, area);
+        frame.render_widget(
+            Paragraph::new(vec![
+                Line::from(format!("{} {} {} {} ({})?", action, position.symbol, format!("{:?}", position.cmd).to_lowercase(), position.volume, position_id)),
+                Line::from("y confirm, any other key cancels"),
+            ])
+            .block(Block::bordered().title(" Confirm ").style(Style::default().fg(Color::Yellow))),
+            area,
+        );
+    }
+}
+
+/// Terminal events read on a blocking thread, the stream socket is polled on the runtime.
+/// Dropping it stops the thread before the terminal is restored, so no key press is lost
+/// to a reader that outlived the dashboard.
+struct Input {
+    receiver: mpsc::UnboundedReceiver<Event>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Input {
+    fn spawn() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+        let thread = std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                match event::poll(INPUT_POLL) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(_) => break,
+                }
+                let Ok(event) = event::read() else {
+                    break;
+                };
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { receiver, shutdown, thread: Some(thread) }
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+async fn close_position(session: &mut Session, dashboard: &mut Dashboard, position_id: u32) -> Result<(), Box<dyn Error>> {
+    let Some(position) = dashboard.positions.get(&position_id) else {
+        return Ok(());
+    };
+    let r#type = if position.is_market() { Type::Close } else { Type::Delete };
+    let price = match (r#type, dashboard.watchlist.get(&position.symbol)) {
+        (Type::Delete, _) => position.open_price,
+        (_, Some(quote)) => if position.cmd == Cmd::Buy { quote.bid } else { quote.ask },
+        (_, None) => {
+            let quote = get_symbol(session.xapi.client(), &position.symbol).await?;
+            if position.cmd == Cmd::Buy { quote.bid } else { quote.ask }
+        }
+    };
+    let trade_trans_info = TradeTransInfo {
+        cmd: position.cmd,
+        custom_comment: None,
+        expiration: 0,
+        offset: 0,
+        order: position.order,
+        price,
+        sl: position.sl,
+        symbol: position.symbol.clone(),
+        tp: position.tp,
+        r#type,
+        volume: position.volume,
+    };
+    session.xapi.client().execute_command(&Request::TradeTransaction(TradeTransaction { trade_trans_info })).await?;
+    let order = session.xapi.client().response_data::<TradeTransactionResponse>().await?.return_data.order;
+    dashboard.push_log(format!("{:?} of position {} sent as order {}", r#type, position_id, order));
+    Ok(())
+}
+
+async fn subscribe(session: &mut Session, symbols: &[String]) -> Result<(), Box<dyn Error>> {
+    let xapi = &mut session.xapi;
+    xapi.subscribe_keep_alive().await?;
+    xapi.subscribe_balance().await?;
+    xapi.subscribe_trades().await?;
+    xapi.subscribe_profits().await?;
+    xapi.subscribe_trade_status().await?;
+    for symbol in symbols {
+        xapi.subscribe_tick_prices(symbol, Some(1), Some(0)).await?;
+    }
+    Ok(())
+}
+
+async fn run(terminal: &mut DefaultTerminal, session: &mut Session, dashboard: &mut Dashboard) -> Result<(), Box<dyn Error>> {
+    let mut input = Input::spawn();
+    loop {
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        tokio::select! {
+            event = session.xapi.next_event() => match event {
+                Ok(event) => dashboard.on_event(event),
+                Err(err) if err.is::<ConnectionClosed>() => {
+                    warn!("stream connection closed, reconnecting");
+                    session.xapi.reconnect().await?;
+                }
+                Err(err) => warn!(error = %err, "stream message skipped"),
+            },
+            Some(event) = input.receiver.recv() => {
+                let Event::Key(key) = event else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(position_id) = dashboard.confirm.take() {
+                    if key.code == KeyCode::Char('y') {
+                        if let Err(err) = close_position(session, dashboard, position_id).await {
+                            dashboard.push_log(format!("Close of position {} failed: {}", position_id, err));
+                        }
+                    }
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Down | KeyCode::Char('j') => dashboard.move_selection(true),
+                    KeyCode::Up | KeyCode::Char('k') => dashboard.move_selection(false),
+                    KeyCode::Char('c') | KeyCode::Delete => dashboard.confirm = dashboard.selected_position(),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Full screen view of quotes, positions, account and trade statuses until `q` is pressed.
+pub async fn dashboard(session: &mut Session, symbols: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut dashboard = Dashboard::default();
+    for trade in get_trades(session.xapi.client()).await? {
+        dashboard.positions.insert(trade.position, Position::from_trade_record(trade));
+    }
+    dashboard.move_selection(false);
+
+    let mut symbols = if symbols.is_empty() { session.config.symbols.clone() } else { symbols };
+    for position in dashboard.positions.values() {
+        if !symbols.contains(&position.symbol) {
+            symbols.push(position.symbol.clone());
+        }
+    }
+
+    let now = Local::now().timestamp_millis();
+    let mut history = HistoryDownloader::new(session.xapi.client());
+    for symbol in &symbols {
+        let day_open = match history.get_chart_range(symbol, Period::D1, now - 7 * DAY_MS, now).await {
+            Ok(candles) => candles.last().map(|candle| candle.open as f32),
+            Err(err) => {
+                warn!(symbol = %symbol, error = %err, "no daily candle for change");
+                None
+            }
+        };
+        dashboard.watchlist.insert(symbol.clone(), Quote { bid: 0.0, ask: 0.0, day_open });
+    }
+
+    subscribe(session, &symbols).await?;
+    dashboard.push_log(format!("Subscribed to {} symbols", symbols.len()));
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, session, &mut dashboard).await;
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::store::CandleStore;
+use crate::xapi_definitions::commands_common::Period;
+use crate::xapi_definitions::commands_main::*;
+use crate::XApiClient;
+
+/// xAPI expects at least 200 ms between consecutive requests.
+pub const MIN_REQUEST_INTERVAL: Duration = Duration::from_millis(200);
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Candle {
+    pub ctm: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub vol: f64,
+}
+
+impl Candle {
+    pub fn from_rate_info(rate_info: &RateInfoRecord, digits: u32) -> Self {
+        let scale = 10f64.powi(digits as i32);
+        let open = rate_info.open / scale;
+        Self {
+            ctm: rate_info.ctm,
+            open,
+            high: open + rate_info.high / scale,
+            low: open + rate_info.low / scale,
+            close: open + rate_info.close / scale,
+            vol: rate_info.vol,
+        }
+    }
+}
+
+/// Longest time range requested in one `getChartRangeRequest` for a given period.
+pub fn max_chunk_span(period: Period) -> i64 {
+    match period {
+        // Smallest span for periods this version does not know, always accepted.
+        Period::M1 | Period::Unknown(_) => 7 * DAY_MS,
+        Period::M5 => 30 * DAY_MS,
+        Period::M15 => 60 * DAY_MS,
+        Period::M30 => 120 * DAY_MS,
+        Period::H1 => 240 * DAY_MS,
+        Period::H4 => 2 * 365 * DAY_MS,
+        Period::D1 | Period::W1 | Period::MN1 => 20 * 365 * DAY_MS,
+    }
+}
+
+/// Splits `[from, to)` into server acceptable ranges.
+pub fn chunks(period: Period, from: i64, to: i64) -> Vec<(i64, i64)> {
+    let span = max_chunk_span(period);
+    let mut chunks = Vec::new();
+    let mut start = from;
+    while start < to {
+        let end = (start + span).min(to);
+        chunks.push((start, end));
+        start = end;
+    }
+    chunks
+}
+
+/// Start of a download of `[from, ..)` into a store whose newest candle opened at `last_ctm`.
+/// Resumes just after that candle rather than one period later: `Period::millis` is fixed,
+/// so for months it would skip over the next candle. The store drops the overlap.
+fn resume_from(from: i64, last_ctm: Option<i64>) -> i64 {
+    match last_ctm {
+        Some(last_ctm) => from.max(last_ctm + 1),
+        None => from,
+    }
+}
+
+pub struct HistoryDownloader<'a> {
+    client: &'a mut XApiClient,
+    min_interval: Duration,
+    last_request: Option<Instant>,
+}
+
+impl<'a> HistoryDownloader<'a> {
+    pub fn new(client: &'a mut XApiClient) -> Self {
+        Self {
+            client,
+            min_interval: MIN_REQUEST_INTERVAL,
+            last_request: None,
+        }
+    }
+
+    pub fn min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    async fn throttle(&mut self) {
+        if let Some(last_request) = self.last_request {
+            tokio::time::sleep_until(last_request + self.min_interval).await;
+        }
+        self.last_request = Some(Instant::now());
+    }
+
+    pub async fn get_chart_range(
+        &mut self,
+        symbol: &str,
+        period: Period,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<Candle>, Box<dyn Error>> {
+        self.throttle().await;
+
+        let request = Request::GetChartRange(
+            GetChartRangeRequest {
+                info: ChartRangeInfoRecord {
+                    end,
+                    period,
+                    start,
+                    symbol: symbol.into(),
+                    ticks: 0,
+                }
+            }
+        );
+        self.client.execute_command(&request).await?;
+        let response = self.client.response_data::<GetChartResponse>().await?;
+        let digits = response.return_data.digits;
+
+        Ok(response.return_data.rate_infos.iter()
+            .map(|rate_info| Candle::from_rate_info(rate_info, digits))
+            .collect())
+    }
+
+    /// Downloads `[from, to)` into `store` chunk by chunk. Candles already present in the
+    /// store are not requested again, so an interrupted download resumes from the last stored bar.
+    /// Returns the number of candles written.
+    pub async fn download<S: CandleStore>(
+        &mut self,
+        symbol: &str,
+        period: Period,
+        from: i64,
+        to: i64,
+        store: &mut S,
+    ) -> Result<usize, Box<dyn Error>> {
+        let start = resume_from(from, store.last_ctm()?);
+
+        let mut written = 0;
+        for (chunk_start, chunk_end) in chunks(period, start, to) {
+            let candles: BTreeMap<i64, Candle> = self
+                .get_chart_range(symbol, period, chunk_start, chunk_end)
+                .await?
+                .into_iter()
+                .filter(|candle| candle.ctm >= chunk_start && candle.ctm < chunk_end)
+                .map(|candle| (candle.ctm, candle))
+                .collect();
+
+            let candles: Vec<Candle> = candles.into_values().collect();
+            written += store.append(&candles)?;
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn millis(year: i32, month: u32, day: u32) -> i64 {
+        Utc.with_ymd_and_hms(year, month, day, 0, 0, 0).unwrap().timestamp_millis()
+    }
+
+    #[test]
+    fn resume_keeps_next_month_candle() {
+        let from = millis(2024, 1, 1);
+        let to = millis(2024, 6, 1);
+        let start = resume_from(from, Some(millis(2024, 2, 1)));
+
+        // One fixed `MN1` period after February 1st is already March 2nd.
+        let march = millis(2024, 3, 1);
+        assert!(millis(2024, 2, 1) + Period::MN1.millis() > march);
+        assert!(chunks(Period::MN1, start, to).iter().any(|(start, end)| (*start..*end).contains(&march)));
+    }
+
+    #[test]
+    fn resume_starts_at_from_when_store_is_empty_or_older() {
+        let from = millis(2024, 1, 1);
+        assert_eq!(resume_from(from, None), from);
+        assert_eq!(resume_from(from, Some(millis(2023, 6, 1))), from);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::Debug;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use ratatui::crossterm::style::Stylize;
+use serde::Deserialize;
+use serde_json::Value;
+
+use xtb::recording::{Channel, Direction, RecordedFrame, Recording};
+use xtb::timestamp_to_datetime;
+use xtb::xapi_definitions::commands_main::*;
+use xtb::xapi_definitions::commands_stream::{RequestStream, ResponseStream};
+
+/// Longest decoded frame printed on one line without `--full`.
+const LINE_WIDTH: usize = 160;
+
+/// Start or end of the inspected window.
+#[derive(Debug, Clone, Copy)]
+pub enum TimeBound {
+    /// Seconds since the recording started.
+    Offset(f64),
+    /// Wall clock time in milliseconds.
+    At(i64),
+}
+
+impl TimeBound {
+    fn millis(&self, first: &RecordedFrame, frame: &RecordedFrame) -> (i64, i64) {
+        match self {
+            TimeBound::Offset(seconds) => ((frame.elapsed_us.saturating_sub(first.elapsed_us) / 1000) as i64, (seconds * 1000.0) as i64),
+            TimeBound::At(millis) => (frame.timestamp, *millis),
+        }
+    }
+}
+
+pub struct Filter {
+    pub command: Option<String>,
+    pub symbol: Option<String>,
+    pub from: Option<TimeBound>,
+    pub to: Option<TimeBound>,
+    pub full: bool,
+}
+
+#[derive(PartialEq)]
+enum Severity {
+    Normal,
+    Error,
+    Unparsable,
+}
+
+/// One frame of the timeline, decoded.
+struct Entry<'a> {
+    frame: &'a RecordedFrame,
+    command: String,
+    custom_tag: Option<String>,
+    latency_ms: Option<f64>,
+    /// Symbols of the frame and, for replies, of their request.
+    symbols: Vec<String>,
+    decoded: String,
+    severity: Severity,
+}
+
+struct Pending {
+    command: String,
+    custom_tag: Option<String>,
+    elapsed_us: u64,
+    symbols: Vec<String>,
+}
+
+#[derive(Default)]
+struct Summary {
+    frames: usize,
+    requests: usize,
+    errors: usize,
+    unparsable: usize,
+    latencies: BTreeMap<String, Vec<f64>>,
+}
+
+/// Prints `path` as a timeline, requests paired with their replies.
+pub fn inspect(path: &Path, filter: &Filter) -> Result<(), Box<dyn Error>> {
+    let recording = Recording::load(path)?;
+    let Some(first) = recording.frames.first() else {
+        println!("Empty recording");
+        return Ok(());
+    };
+    let color = std::io::stdout().is_terminal();
+
+    let mut pending: HashMap<u64, VecDeque<Pending>> = HashMap::new();
+    let mut summary = Summary::default();
+    for frame in &recording.frames {
+        let entry = decode(frame, pending.entry(frame.connection).or_default(), filter.full);
+        if !matches(&entry, first, filter) {
+            continue;
+        }
+        summary.add(&entry);
+        print_entry(&entry, first, color);
+    }
+
+    let unanswered: usize = pending.values().map(VecDeque::len).sum();
+    println!();
+    println!(
+        "{} frames, {} requests, {} unanswered, {} errors, {} unparsable",
+        summary.frames, summary.requests, unanswered, summary.errors, summary.unparsable,
+    );
+    for (command, latencies) in &summary.latencies {
+        let average = latencies.iter().sum::<f64>() / latencies.len() as f64;
+        let max = latencies.iter().cloned().fold(0.0, f64::max);
+        println!("  {:<24} {:>5}x  avg {:>9.3} ms  max {:>9.3} ms", command, latencies.len(), average, max);
+    }
+    Ok(())
+}
+
+impl Summary {
+    fn add(&mut self, entry: &Entry) {
+        self.frames += 1;
+        if entry.frame.direction == Direction::Sent {
+            self.requests += 1;
+        }
+        match entry.severity {
+            Severity::Error => self.errors += 1,
+            Severity::Unparsable => self.unparsable += 1,
+            Severity::Normal => {}
+        }
+        if let Some(latency) = entry.latency_ms {
+            self.latencies.entry(entry.command.clone()).or_default().push(latency);
+        }
+    }
+}
+
+fn matches(entry: &Entry, first: &RecordedFrame, filter: &Filter) -> bool {
+    if filter.command.as_ref().is_some_and(|command| !command.eq_ignore_ascii_case(&entry.command)) {
+        return false;
+    }
+    if filter.symbol.as_ref().is_some_and(|symbol| !entry.symbols.iter().any(|s| s.eq_ignore_ascii_case(symbol))) {
+        return false;
+    }
+    if let Some(from) = filter.from {
+        let (at, bound) = from.millis(first, entry.frame);
+        if at < bound {
+            return false;
+        }
+    }
+    if let Some(to) = filter.to {
+        let (at, bound) = to.millis(first, entry.frame);
+        if at > bound {
+            return false;
+        }
+    }
+    true
+}
+
+fn decode<'a>(frame: &'a RecordedFrame, pending: &mut VecDeque<Pending>, full: bool) -> Entry<'a> {
+    let mut entry = Entry {
+        frame,
+        command: "?".into(),
+        custom_tag: None,
+        latency_ms: None,
+        symbols: Vec::new(),
+        decoded: String::new(),
+        severity: Severity::Normal,
+    };
+    let value = match serde_json::from_str::<Value>(&frame.frame) {
+        Ok(value) => value,
+        Err(err) => {
+            entry.severity = Severity::Unparsable;
+            entry.decoded = format!("{} in {:?}", err, frame.frame);
+            if frame.direction == Direction::Received && frame.channel == Channel::Main {
+                // The client gives up on the oldest request when a reply does not parse.
+                if let Some(request) = pending.pop_front() {
+                    entry.latency_ms = Some(frame.elapsed_us.saturating_sub(request.elapsed_us) as f64 / 1000.0);
+                    entry.command = request.command;
+                }
+            }
+            return entry;
+        }
+    };
+    collect_symbols(&value, &mut entry.symbols);
+    entry.custom_tag = value["customTag"].as_str().map(String::from);
+
+    match (frame.channel, frame.direction) {
+        (Channel::Main, Direction::Sent) => {
+            entry.command = value["command"].as_str().unwrap_or("?").into();
+            entry.decoded = typed::<Request>(&value, full, &mut entry.severity);
+            pending.push_back(Pending {
+                command: entry.command.clone(),
+                custom_tag: entry.custom_tag.clone(),
+                elapsed_us: frame.elapsed_us,
+                symbols: entry.symbols.clone(),
+            });
+        }
+        (Channel::Main, Direction::Received) => {
+            let index = match &entry.custom_tag {
+                Some(custom_tag) => pending.iter().position(|request| request.custom_tag.as_ref() == Some(custom_tag)),
+                None => (!pending.is_empty()).then_some(0),
+            };
+            if let Some(request) = index.and_then(|index| pending.remove(index)) {
+                entry.latency_ms = Some(frame.elapsed_us.saturating_sub(request.elapsed_us) as f64 / 1000.0);
+                entry.symbols.extend(request.symbols);
+                entry.command = request.command;
+            }
+            entry.decoded = decode_reply(&entry.command, &value, full, &mut entry.severity);
+        }
+        (Channel::Stream, Direction::Sent) => {
+            entry.command = value["command"].as_str().unwrap_or("?").into();
+            entry.decoded = typed::<RequestStream>(&value, full, &mut entry.severity);
+        }
+        (Channel::Stream, Direction::Received) => {
+            entry.command = value["command"].as_str().unwrap_or("?").into();
+            entry.decoded = typed::<ResponseStream>(&value, full, &mut entry.severity);
+        }
+    }
+    entry
+}
+
+/// Reply of `command` decoded into its response type, `ErrorResponse` when `status` is false.
+fn decode_reply(command: &str, value: &Value, full: bool, severity: &mut Severity) -> String {
+    if value["status"] == Value::Bool(false) {
+        *severity = Severity::Error;
+        return typed::<ErrorResponse>(value, full, &mut Severity::Normal);
+    }
+    match command {
+        "login" => typed::<LoginResponse>(value, full, severity),
+        "getAllSymbols" => typed::<GetResponse<Vec<SymbolRecord>>>(value, full, severity),
+        "getSymbol" => typed::<GetResponse<SymbolRecord>>(value, full, severity),
+        "getChartLast" | "getChartRangeRequest" => typed::<GetResponse<GetChartResponse>>(value, full, severity),
+        "getCommissionDef" => typed::<GetResponse<GetCommissionDefResponse>>(value, full, severity),
+        "getCurrentUserData" => typed::<GetResponse<GetCurrentUserDataResponse>>(value, full, severity),
+        "getMarginLevel" => typed::<GetResponse<GetMarginLevelResponse>>(value, full, severity),
+        "getMarginTrade" => typed::<GetResponse<GetMarginTradeResponse>>(value, full, severity),
+        "getTrades" | "getTradesHistory" => typed::<GetResponse<Vec<TradeRecord>>>(value, full, severity),
+        "tradeTransaction" => typed::<GetResponse<TradeTransactionResponse>>(value, full, severity),
+        "tradeTransactionStatus" => typed::<GetResponse<TradeTransactionStatusResponse>>(value, full, severity),
+        _ => show(value, full),
+    }
+}
+
+fn typed<T: Debug + for<'de> Deserialize<'de>>(value: &Value, full: bool, severity: &mut Severity) -> String {
+    match serde_path_to_error::deserialize::<_, T>(value) {
+        Ok(typed) => show(&typed, full),
+        Err(err) => {
+            *severity = Severity::Unparsable;
+            format!("{}: {}", err, value)
+        }
+    }
+}
+
+fn show<T: Debug>(value: &T, full: bool) -> String {
+    if full {
+        return format!("{:#?}", value);
+    }
+    let line = format!("{:?}", value);
+    match line.char_indices().nth(LINE_WIDTH) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line,
+    }
+}
+
+fn collect_symbols(value: &Value, symbols: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                match (key.as_str(), value) {
+                    ("symbol", Value::String(symbol)) if !symbols.contains(symbol) => symbols.push(symbol.clone()),
+                    _ => collect_symbols(value, symbols),
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_symbols(value, symbols)),
+        _ => {}
+    }
+}
+
+fn print_entry(entry: &Entry, first: &RecordedFrame, color: bool) {
+    let frame = entry.frame;
+    let offset = frame.elapsed_us.saturating_sub(first.elapsed_us) as f64 / 1_000_000.0;
+    let arrow = match frame.direction {
+        Direction::Sent => "→",
+        Direction::Received => "←",
+    };
+    let channel = match frame.channel {
+        Channel::Main => "main",
+        Channel::Stream => "stream",
+    };
+    let tag = entry.custom_tag.as_deref().map(|tag| format!(" #{}", tag)).unwrap_or_default();
+    let latency = entry.latency_ms.map(|latency| format!(" {:.3} ms", latency)).unwrap_or_default();
+    let head = format!(
+        "{:>10.3}s {} [{} {}] {} {}{}{}",
+        offset, timestamp_to_datetime(frame.timestamp), frame.connection, channel, arrow, entry.command, tag, latency,
+    );
+    let label = match entry.severity {
+        Severity::Normal => "",
+        Severity::Error => " ERROR",
+        Severity::Unparsable => " UNPARSABLE",
+    };
+    let line = format!("{}{}  {}", head, label, entry.decoded);
+    if !color {
+        println!("{}", line);
+        return;
+    }
+    match (&entry.severity, frame.direction) {
+        (Severity::Error, _) => println!("{}", line.red()),
+        (Severity::Unparsable, _) => println!("{}", line.magenta()),
+        (Severity::Normal, Direction::Sent) => println!("{}", line.cyan()),
+        (Severity::Normal, Direction::Received) => println!("{}", line),
+    }
+}
@@ -1,16 +1,41 @@
 use std::error::Error;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use webpki_roots;
-
-use tokio::net::TcpStream;
-use tokio_rustls::client::TlsStream;
-use tokio_rustls::{rustls, TlsConnector};
 
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::ops::Range;
 use std::sync::Arc; use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tracing::{debug, info, trace, warn, Instrument};
 
+pub mod accounts;
+pub mod backtest;
+pub mod broker;
+pub mod config;
+pub mod history;
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod read_only;
+pub mod recording;
+pub mod risk;
+pub mod secret;
+pub mod session;
+pub mod sim;
+pub mod store;
+pub mod strategy;
+pub mod ticks;
+pub mod transport;
+pub mod vault;
 pub mod xapi_definitions;
 use xapi_definitions::*;
 use xapi_definitions::commands_main::*;
+use xapi_definitions::commands_stream::{RequestStream, ResponseStream};
+use metrics::{Metrics, NoopMetrics};
+use recording::{Channel, ConnectionRecorder, Direction, Recorder};
+use secret::Secret;
+use ticks::{StreamMessage, TickParser};
+use transport::{AsyncStream, ConnectOptions, Proxy};
 
 use chrono::prelude::*;
 
@@ -25,93 +50,406 @@ pub fn timestamp_to_datetime(timestamp: i64) -> String {
     }
 }
 
-pub struct XApiClient {
-    pub socket: TlsStream<TcpStream>,
+const RES_BUF_SIZE: usize = 4096;
+const FRAME_TERMINATOR: &[u8] = b"\n\n";
+
+struct PendingCommand {
+    command: String,
+    custom_tag: String,
+    sent: Instant,
 }
 
-impl XApiClient {
-    pub async fn new(xapi_address: &str, xapi_port: &str) -> Result<Self, Box<dyn Error>> {
+/// Typestate of `XApiClient`: not connected yet.
+pub struct Disconnected;
+/// Typestate of `XApiClient`: connected, only `login` is available.
+pub struct Connected;
+/// Typestate of `XApiClient`: logged in, data and trading commands are available.
+pub struct Authenticated;
 
-        let root_cert_store = rustls::RootCertStore {
-            roots: webpki_roots::TLS_SERVER_ROOTS.iter().cloned().collect(),
-        };
+/// Error of a read on a connection the server has closed, no further frame will come.
+/// Match it with `err.is::<ConnectionClosed>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionClosed;
 
-        let config = rustls::ClientConfig::builder()
-            .with_root_certificates(root_cert_store)
-            .with_no_client_auth();
+impl std::fmt::Display for ConnectionClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Connection closed")
+    }
+}
 
-        let address = format!("{}:{}", xapi_address, xapi_port);
+impl Error for ConnectionClosed {}
 
-        let connector = TlsConnector::from(Arc::new(config));
-        let socket = TcpStream::connect(address.as_str()).await?;
-        let socket 
-            = connector.connect(pki_types::ServerName::try_from(xapi_address)?.to_owned(), socket).await?;
+/// Socket with framing, correlation of tagged replies, logging and metrics,
+/// shared by the main and the stream connections.
+struct Connection {
+    socket: Box<dyn AsyncStream>,
+    buffer: Vec<u8>,
+    /// Bytes at the front of `buffer` already returned as frames, dropped on the next read.
+    consumed: usize,
+    address: String,
+    log_payloads: bool,
+    next_tag: u64,
+    pending: VecDeque<PendingCommand>,
+    metrics: Arc<dyn Metrics>,
+    recorder: Option<ConnectionRecorder>,
+}
 
-        Ok(Self { socket: socket })
+impl Connection {
+    #[tracing::instrument(name = "connect", skip_all, fields(address = xapi_address, port = xapi_port, plaintext = options.is_plaintext()))]
+    async fn open(
+        xapi_address: &str,
+        xapi_port: &str,
+        options: &ConnectOptions,
+        metrics: Arc<dyn Metrics>,
+        log_payloads: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let socket = options.open(xapi_address, xapi_port).await?;
+        info!("connected");
+        Ok(Self::over(socket, format!("{}:{}", xapi_address, xapi_port), metrics, log_payloads))
     }
 
-    pub async fn execute_command<T: Execute + Serialize>(
-        &mut self,
-        request: &T,
-    ) -> Result<(), Box<dyn Error>> {
-        let request = request.command()?;
-        self.socket.write_all(&request.as_bytes()).await?;
+    /// Connection on an already open stream, `address` only labels logs, metrics and recordings.
+    fn over(socket: Box<dyn AsyncStream>, address: String, metrics: Arc<dyn Metrics>, log_payloads: bool) -> Self {
+        metrics.connect(&address);
+        Self {
+            socket,
+            buffer: Vec::new(),
+            consumed: 0,
+            address,
+            log_payloads,
+            next_tag: 0,
+            pending: VecDeque::new(),
+            metrics,
+            recorder: None,
+        }
+    }
+
+    fn record(&mut self, recorder: Option<&Recorder>, channel: Channel) {
+        self.recorder = recorder.map(|recorder| recorder.connection(channel, &self.address));
+    }
 
+    async fn write(&mut self, request: &str) -> Result<(), Box<dyn Error>> {
+        if self.log_payloads {
+            trace!(address = %self.address, payload = %secret::redact(request), "request");
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Sent, request);
+        }
+        self.socket.write_all(request.as_bytes()).await?;
         Ok(())
     }
 
-    async fn get_response<T: ValidResponse + Serialize + for<'de> Deserialize<'de>> (
-        &mut self,
-        response_size: usize,
-    ) -> Result<Response<T>, Box<dyn Error>> {
-        let mut buf = vec![0; response_size];
+    async fn execute_command(&mut self, request: &Request) -> Result<(), Box<dyn Error>> {
+        self.next_tag += 1;
+        let custom_tag = self.next_tag.to_string();
+        let (request, command) = request.command_tagged(&custom_tag)?;
+        debug!(address = %self.address, command = %command, custom_tag = %custom_tag, "command");
+        self.metrics.request(&command);
+        self.write(&request).await?;
+        self.pending.push_back(PendingCommand { command, custom_tag, sent: Instant::now() });
 
-        match self.socket.read(&mut buf).await {
+        Ok(())
+    }
+
+    fn complete_command(&mut self, custom_tag: Option<&str>) {
+        let index = match custom_tag {
+            Some(custom_tag) => self.pending.iter().position(|pending| pending.custom_tag == custom_tag),
+            None => (!self.pending.is_empty()).then_some(0),
+        };
+        if let Some(pending) = index.and_then(|index| self.pending.remove(index)) {
+            self.metrics.response(&pending.command, pending.sent.elapsed());
+            debug!(
+                address = %self.address,
+                command = %pending.command,
+                custom_tag = %pending.custom_tag,
+                latency_ms = pending.sent.elapsed().as_secs_f64() * 1000.0,
+                "response",
+            );
+        }
+    }
+
+    /// Reads one complete reply frame. xAPI terminates every JSON message with
+    /// an empty line, so a single socket read may hold a partial frame or several.
+    async fn read_frame(&mut self) -> Result<String, Box<dyn Error>> {
+        let frame = self.read_frame_in_place().await?;
+        self.frame(frame).map(String::from)
+    }
+
+    /// Like `read_frame`, but leaves the frame in the connection buffer, valid until the
+    /// next read, so no allocation is made once the buffer has grown. See `frame`.
+    async fn read_frame_in_place(&mut self) -> Result<Range<usize>, Box<dyn Error>> {
+        self.buffer.drain(..std::mem::take(&mut self.consumed));
+        let (start, end) = loop {
+            let start = self.consumed;
+            if let Some(pos) = self.buffer[start..].windows(FRAME_TERMINATOR.len()).position(|w| w == FRAME_TERMINATOR) {
+                self.consumed = start + pos + FRAME_TERMINATOR.len();
+                if self.buffer[start..start + pos].trim_ascii().is_empty() {
+                    continue;
+                }
+                break (start, start + pos);
+            }
+
+            self.fill().await?;
+        };
+        Ok(start..end)
+    }
+
+    /// Appends one socket read to `buffer`.
+    async fn fill(&mut self) -> Result<(), Box<dyn Error>> {
+        self.buffer.reserve(RES_BUF_SIZE);
+        match self.socket.read_buf(&mut self.buffer).await {
             // Return value of `Ok(0)` signifies that the remote has closed
             Ok(0) => {
-                eprintln!("Connection closed");
-                return Err("error")?;
-            }
-            Ok(n) => {
-                let str =  String::from_utf8((&buf[..n]).to_vec())?;
-                println!("Response raw [{}]: {:?}", n, str);
-                match serde_json::from_str::<Response<T>>(&str) {
-                    Ok(res) => {
-                        Ok(res)
-                    }
-                    Err(err) => {
-                        //eprintln!("Failed to convert response request -> {:?}\n{:?}", str, err);
-                        let error = format!("Failed to convert response request -> {}. {}", str, err);
-                        Err(error)?
-                    }
-                }
+                warn!(address = %self.address, "connection closed");
+                Err(ConnectionClosed)?
             }
+            Ok(_) => Ok(()),
             Err(e) => {
                 // Unexpected socket error. There isn't much we can do here so just stop processing.
-                eprintln!("Failed to read from socket err = {:?}", e);
-                return Err(e)?;
+                warn!(address = %self.address, error = %e, "failed to read from socket");
+                Err(e)?
             }
         }
     }
 
-//    pub async fn response_login <T: ValidResponse + Serialize + for<'de> Deserialize<'de>> (
-    pub async fn response_login (
+    /// Frame read by `read_frame_in_place`, recorded as received.
+    fn frame(&self, frame: Range<usize>) -> Result<&str, Box<dyn Error>> {
+        let frame = std::str::from_utf8(&self.buffer[frame])?;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Received, frame);
+        }
+        Ok(frame)
+    }
+
+    async fn get_response<T: ValidResponse + Serialize + for<'de> Deserialize<'de>> (
         &mut self,
-    ) -> Result <LoginResponse, Box<dyn Error>> {
-        match self.get_response::<LoginResponse>(1024).await? {
-            Response::Login(res) => {
+    ) -> Result<Response<T>, Box<dyn Error>> {
+        let str = self.read_frame().await?;
+        if self.log_payloads {
+            trace!(address = %self.address, size = str.len(), payload = %secret::redact(&str), "response");
+        }
+        match serde_json::from_str::<Response<T>>(&str) {
+            Ok(res) => {
+                let custom_tag = match &res {
+                    Response::Login(res) => res.custom_tag.as_deref(),
+                    Response::Data(res) => res.custom_tag.as_deref(),
+                    Response::Error(res) => res.custom_tag.as_deref(),
+                };
+                let custom_tag = custom_tag.map(String::from);
+                self.complete_command(custom_tag.as_deref());
+                if let Response::Error(err) = &res {
+                    self.metrics.server_error(&err.error_code);
+                }
                 Ok(res)
             }
-            Response::Error(err) => {
-                eprintln!("Login error: {:?}", err);
-                let error = format!("Login error: {} - {}", err.error_code, err.error_descr);
-               // return Err(error)?;
+            Err(err) => {
+                self.complete_command(None);
+                self.metrics.unparsable_frame("response");
+                warn!(address = %self.address, error = %err, "failed to parse response");
+                let error = format!("Failed to convert response request -> {}. {}", secret::redact(&str), err);
                 Err(error)?
             }
-            _ => {
-                Err("Response does not match request")?
+        }
+    }
+
+    /// Appends up to `response_size` bytes of whatever arrives next, framed or not. Bytes come
+    /// from the frame buffer first, so they stay in order with `read_frame`. Not recorded,
+    /// as they need not be a whole frame.
+    async fn get_response_raw (
+        &mut self,
+        response_raw: &mut String,
+        response_size: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.buffer.drain(..std::mem::take(&mut self.consumed));
+        if self.buffer.is_empty() {
+            self.fill().await?;
+        }
+        let mut n = response_size.min(self.buffer.len());
+        // Never split a UTF-8 sequence, the rest of it comes with the next call.
+        while n > 0 && n < self.buffer.len() && self.buffer[n] & 0xC0 == 0x80 {
+            n -= 1;
+        }
+        response_raw.push_str(std::str::from_utf8(&self.buffer[..n])?);
+        self.buffer.drain(..n);
+        Ok(())
+    }
+}
+
+/// Main xAPI connection. The type parameter tracks the session state so data and
+/// trading commands only compile on a logged-in client:
+/// `XApiClient::new(..).connect().await?.login(&request).await?`.
+pub struct XApiClient<S = Authenticated> {
+    address: String,
+    port: String,
+    metrics: Arc<dyn Metrics>,
+    log_payloads: bool,
+    connection: Option<Connection>,
+    stream_session_id: Option<Secret>,
+    read_only: bool,
+    options: ConnectOptions,
+    recorder: Option<Recorder>,
+    state: PhantomData<S>,
+}
+
+impl<S> XApiClient<S> {
+    fn into_state<T>(self) -> XApiClient<T> {
+        XApiClient {
+            address: self.address,
+            port: self.port,
+            metrics: self.metrics,
+            log_payloads: self.log_payloads,
+            connection: self.connection,
+            stream_session_id: self.stream_session_id,
+            read_only: self.read_only,
+            options: self.options,
+            recorder: self.recorder,
+            state: PhantomData,
+        }
+    }
+
+    fn connection(&mut self) -> &mut Connection {
+        self.connection.as_mut().expect("connected state without a connection")
+    }
+
+    /// Reports to `metrics` from now on.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        if let Some(connection) = self.connection.as_mut() {
+            connection.metrics = metrics.clone();
+        }
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Records every frame sent and received to `recorder` from now on, including
+    /// the stream connections opened by this client.
+    pub fn record(mut self, recorder: Recorder) -> Self {
+        if let Some(connection) = self.connection.as_mut() {
+            connection.record(Some(&recorder), Channel::Main);
+        }
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Logs redacted request and response payloads at `trace` level. Off by default.
+    pub fn log_payloads(&mut self, log_payloads: bool) {
+        if let Some(connection) = self.connection.as_mut() {
+            connection.log_payloads = log_payloads;
+        }
+        self.log_payloads = log_payloads;
+    }
+}
+
+impl XApiClient<Disconnected> {
+    pub fn new(xapi_address: &str, xapi_port: &str) -> Self {
+        Self {
+            address: xapi_address.into(),
+            port: xapi_port.into(),
+            metrics: Arc::new(NoopMetrics),
+            log_payloads: false,
+            connection: None,
+            stream_session_id: None,
+            read_only: false,
+            options: ConnectOptions::default(),
+            recorder: None,
+            state: PhantomData,
+        }
+    }
+
+    /// Refuses `TradeTransaction` requests at runtime, for the whole life of the client.
+    /// There is no way to lift it, see `read_only::ReadOnlyClient` for a client without
+    /// trade methods.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// TLS, timeout and transport settings used by `connect` and `connect_stream`.
+    /// Replaces all previous settings, including `plaintext` and `proxy`; start from
+    /// `ClientConfig::connect_options` to keep those of a profile.
+    pub fn connect_options(mut self, options: ConnectOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Plain TCP without TLS, for local servers such as `mock::MockServer`. Changes only
+    /// this setting of the current options, so call it after `connect_options`.
+    pub fn plaintext(mut self, plaintext: bool) -> Self {
+        self.options = self.options.plaintext(plaintext);
+        self
+    }
+
+    /// Tunnels the main and stream connections through `proxy`. Like `plaintext`, call it
+    /// after `connect_options`.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.options = self.options.proxy(proxy);
+        self
+    }
+
+    pub async fn connect(mut self) -> Result<XApiClient<Connected>, Box<dyn Error>> {
+        let mut connection = Connection::open(&self.address, &self.port, &self.options, self.metrics.clone(), self.log_payloads).await?;
+        connection.record(self.recorder.as_ref(), Channel::Main);
+        self.connection = Some(connection);
+        Ok(self.into_state())
+    }
+
+    /// Uses `stream` as the main connection instead of connecting, any
+    /// `AsyncRead + AsyncWrite` transport works.
+    pub fn connect_over<T: AsyncStream + 'static>(mut self, stream: T) -> XApiClient<Connected> {
+        let address = format!("{}:{}", self.address, self.port);
+        let mut connection = Connection::over(Box::new(stream), address, self.metrics.clone(), self.log_payloads);
+        connection.record(self.recorder.as_ref(), Channel::Main);
+        self.connection = Some(connection);
+        self.into_state()
+    }
+}
+
+impl XApiClient<Connected> {
+    /// Sends the login request and reads the reply.
+    pub async fn login(mut self, login_request: &Request) -> Result<XApiClient<Authenticated>, Box<dyn Error>> {
+        if !matches!(login_request, Request::Login(_)) {
+            Err("Expected a login request")?;
+        }
+        let span = tracing::info_span!("login", address = %self.connection().address);
+        let response_login = async {
+            let connection = self.connection();
+            connection.execute_command(login_request).await?;
+            match connection.get_response::<LoginResponse>().await? {
+                Response::Login(res) => {
+                    info!("logged in");
+                    Ok::<_, Box<dyn Error>>(res)
+                }
+                Response::Error(err) => {
+                    warn!(error_code = %err.error_code, error_descr = %err.error_descr, "login error");
+                    let error = format!("Login error: {} - {}", err.error_code, err.error_descr);
+                    Err(error)?
+                }
+                _ => {
+                    Err("Response does not match request")?
+                }
             }
         }
+        .instrument(span)
+        .await?;
+
+        self.stream_session_id = Some(response_login.stream_session_id);
+        Ok(self.into_state())
+    }
+}
+
+impl XApiClient<Authenticated> {
+    pub async fn execute_command(&mut self, request: &Request) -> Result<(), Box<dyn Error>> {
+        if matches!(request, Request::Login(_)) {
+            Err("Already logged in")?;
+        }
+        if self.read_only && matches!(request, Request::TradeTransaction(_)) {
+            warn!(address = %self.address, "trade transaction refused on read-only client");
+            Err("Trade transactions are disabled, the client is read-only")?;
+        }
+        self.connection().execute_command(request).await
     }
 
 //    pub async fn response_data <T: ValidResponse + Serialize + for<'de> Deserialize<'de>> (
@@ -124,12 +462,12 @@ impl XApiClient {
     {
         //self.execute_command(request).await?;
 
-        match self.get_response::<T>(1024).await? {
+        match self.connection().get_response::<T>().await? {
             Response::Data(res) => {
                 Ok(res)
             }
             Response::Error(err) => {
-                //eprintln!("Login error: {:?}", err);
+                warn!(error_code = %err.error_code, error_descr = %err.error_descr, "data error");
                 let error = format!("Data error: {} - {}", err.error_code, err.error_descr);
                 Err(error)?
             }
@@ -139,17 +477,120 @@ impl XApiClient {
         }
     }
 
+    /// Reads one reply frame without parsing it.
+    pub async fn read_frame(&mut self) -> Result<String, Box<dyn Error>> {
+        self.connection().read_frame().await
+    }
+
     pub async fn get_response_raw (
         &mut self,
         response_raw: &mut String,
         response_size: usize,
     ) -> Result<(), Box<dyn Error>> {
-        let mut buf = vec![0; response_size];
-        let n = self.socket.read(&mut buf).await?;
-        let str = String::from_utf8((&buf[..n]).to_vec())?;
-        response_raw.push_str(&str);
-        //println!("Response raw <- {:?}", str);
-        Ok(())
+        self.connection().get_response_raw(response_raw, response_size).await
     }
 
+    /// Opens the stream connection of this login on `stream_port` of the same host.
+    pub async fn connect_stream(&self, stream_port: &str) -> Result<StreamConnection, Box<dyn Error>> {
+        let connection = Connection::open(&self.address, stream_port, &self.options, self.metrics.clone(), self.log_payloads).await?;
+        Ok(self.stream_connection(connection))
+    }
+
+    /// Stream connection of this login on an already open `stream`.
+    pub fn connect_stream_over<T: AsyncStream + 'static>(&self, stream: T) -> StreamConnection {
+        let address = format!("{}:stream", self.address);
+        self.stream_connection(Connection::over(Box::new(stream), address, self.metrics.clone(), self.log_payloads))
+    }
+
+    fn stream_connection(&self, mut connection: Connection) -> StreamConnection {
+        connection.record(self.recorder.as_ref(), Channel::Stream);
+        StreamConnection {
+            connection,
+            stream_session_id: self.stream_session_id.clone().expect("authenticated without a stream session id"),
+        }
+    }
+}
+
+/// Stream connection bound to a login, the only place subscriptions can be sent.
+pub struct StreamConnection {
+    connection: Connection,
+    stream_session_id: Secret,
+}
+
+impl StreamConnection {
+    /// Logs redacted payloads at `trace` level. Off by default.
+    pub fn log_payloads(&mut self, log_payloads: bool) {
+        self.connection.log_payloads = log_payloads;
+    }
+
+    /// Sends the subscription with the stream session id of the login.
+    pub async fn subscribe(&mut self, request: &RequestStream) -> Result<(), Box<dyn Error>> {
+        let (request, command) = request.command_in_session(&self.stream_session_id)?;
+        self.connection.metrics.request(&command);
+        self.connection.write(&request).await
+    }
+
+    pub async fn read_frame(&mut self) -> Result<String, Box<dyn Error>> {
+        self.connection.read_frame().await
+    }
+
+    pub async fn response_stream (
+        &mut self,
+    ) -> Result <ResponseStream, Box<dyn Error>> {
+        let connection = &mut self.connection;
+        let str = connection.read_frame().await?;
+        if connection.log_payloads {
+            trace!(address = %connection.address, size = str.len(), payload = %secret::redact(&str), "stream message");
+        }
+        match serde_json::from_str::<ResponseStream>(&str) {
+            Ok(res) => {
+                debug!(address = %connection.address, command = res.command(), symbol = res.symbol(), "stream message");
+                connection.metrics.stream_message(res.command(), res.symbol());
+                if let ResponseStream::TickPrices(tick) = &res {
+                    let delay = Utc::now().timestamp_millis() - tick.data.timestamp;
+                    connection.metrics.tick_delay(&tick.data.symbol, std::time::Duration::from_millis(delay.max(0) as u64));
+                }
+                Ok(res)
+            }
+            Err(err) => {
+                connection.metrics.unparsable_frame("stream");
+                warn!(address = %connection.address, error = %err, "failed to parse stream message");
+                let error = format!("Failed to convert response stream -> {}. {}", secret::redact(&str), err);
+                Err(error)?
+            }
+        }
+    }
+
+    /// Next message with ticks and candles on the allocation-free path of `parser`,
+    /// for high-frequency consumers. The frame is parsed inside the connection buffer.
+    pub async fn response_stream_fast(
+        &mut self,
+        parser: &mut TickParser,
+    ) -> Result<StreamMessage, Box<dyn Error>> {
+        let frame = self.connection.read_frame_in_place().await?;
+        let connection = &mut self.connection;
+        let str = connection.frame(frame.clone())?;
+        if connection.log_payloads {
+            trace!(address = %connection.address, size = str.len(), payload = %secret::redact(str), "stream message");
+        }
+        match parser.parse_in_place(&mut connection.buffer[frame.clone()]) {
+            Ok(res) => {
+                let symbol = res.symbol(parser.symbols());
+                debug!(address = %connection.address, command = res.command(), symbol, "stream message");
+                connection.metrics.stream_message(res.command(), symbol);
+                if let StreamMessage::Tick(tick) = &res {
+                    let delay = Utc::now().timestamp_millis() - tick.timestamp;
+                    connection.metrics.tick_delay(parser.symbols().name(tick.symbol), std::time::Duration::from_millis(delay.max(0) as u64));
+                }
+                Ok(res)
+            }
+            Err(err) => {
+                connection.metrics.unparsable_frame("stream");
+                warn!(address = %connection.address, error = %err, "failed to parse stream message");
+                let frame = String::from_utf8_lossy(&connection.buffer[frame]);
+                let error = format!("Failed to convert response stream -> {}. {}", secret::redact(&frame), err);
+                Err(error)?
+            }
+        }
+    }
 }
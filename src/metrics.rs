@@ -0,0 +1,313 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Hooks called by `XApiClient`. Every method defaults to a no-op so
+/// implementations only override what they collect.
+pub trait Metrics: Send + Sync {
+    fn request(&self, _command: &str) {}
+    fn response(&self, _command: &str, _latency: Duration) {}
+    fn server_error(&self, _error_code: &str) {}
+    fn connect(&self, _address: &str) {}
+    fn reconnect(&self, _address: &str) {}
+    fn stream_message(&self, _command: &str, _symbol: Option<&str>) {}
+    /// `kind` is `response` or `stream`.
+    fn unparsable_frame(&self, _kind: &str) {}
+    /// Receive time minus `GetTickPricesResponse.timestamp`.
+    fn tick_delay(&self, _symbol: &str, _delay: Duration) {}
+}
+
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+pub const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+enum Series {
+    Counter(f64),
+    Histogram { buckets: Vec<u64>, sum: f64, count: u64 },
+}
+
+impl Series {
+    fn histogram() -> Self {
+        Series::Histogram { buckets: vec![0; LATENCY_BUCKETS.len()], sum: 0.0, count: 0 }
+    }
+}
+
+/// Series keyed by label values, one level per label, so an existing series is found
+/// from borrowed values and only a new one allocates. Hooks run once per tick.
+enum Node {
+    Series(Series),
+    Values(BTreeMap<Box<str>, Node>),
+}
+
+impl Node {
+    fn new(labels: usize, series: fn() -> Series) -> Self {
+        if labels == 0 { Node::Series(series()) } else { Node::Values(BTreeMap::new()) }
+    }
+
+    /// Series at `values`, `None` if there are more or fewer values than labels.
+    fn series(&mut self, values: &[&str], new: fn() -> Series) -> Option<&mut Series> {
+        match (self, values.split_first()) {
+            (Node::Series(series), None) => Some(series),
+            (Node::Values(children), Some((value, rest))) => {
+                if !children.contains_key(*value) {
+                    children.insert((*value).into(), Node::new(rest.len(), new));
+                }
+                children.get_mut(*value)?.series(rest, new)
+            }
+            _ => None,
+        }
+    }
+
+    fn for_each<'a>(&'a self, values: &mut Vec<&'a str>, visit: &mut impl FnMut(&[&str], &Series)) {
+        match self {
+            Node::Series(series) => visit(values, series),
+            Node::Values(children) => {
+                for (value, child) in children {
+                    values.push(value);
+                    child.for_each(values, visit);
+                    values.pop();
+                }
+            }
+        }
+    }
+}
+
+struct Family {
+    help: &'static str,
+    histogram: bool,
+    labels: &'static [&'static str],
+    series: Node,
+}
+
+impl Family {
+    fn new_series(histogram: bool) -> fn() -> Series {
+        if histogram { Series::histogram } else { || Series::Counter(0.0) }
+    }
+
+    /// Series of `values`, one per label. Any other count is a bug in the hook and is
+    /// dropped in release builds rather than mixed into the tree.
+    fn series(&mut self, values: &[&str]) -> Option<&mut Series> {
+        debug_assert_eq!(self.labels.len(), values.len(), "metric needs a value per label");
+        if values.len() != self.labels.len() {
+            return None;
+        }
+        self.series.series(values, Self::new_series(self.histogram))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[&'static str], values: &[&str], extra: Option<(&str, String)>) -> String {
+    let mut parts: Vec<String> = labels.iter().zip(values)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    if let Some((name, value)) = extra {
+        parts.push(format!("{}=\"{}\"", name, value));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+/// In-memory counters and histograms rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct PrometheusMetrics {
+    families: Mutex<BTreeMap<&'static str, Family>>,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    fn family<'a>(
+        families: &'a mut BTreeMap<&'static str, Family>,
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        histogram: bool,
+    ) -> &'a mut Family {
+        let family = families.entry(name).or_insert_with(|| Family {
+            help,
+            histogram,
+            labels,
+            series: Node::new(labels.len(), Family::new_series(histogram)),
+        });
+        debug_assert_eq!(family.labels, labels, "metric {} used with different labels", name);
+        family
+    }
+
+    fn inc(&self, name: &'static str, help: &'static str, labels: &'static [&'static str], values: &[&str]) {
+        let mut families = self.families.lock().unwrap();
+        let family = Self::family(&mut families, name, help, labels, false);
+        if let Some(Series::Counter(value)) = family.series(values) {
+            *value += 1.0;
+        }
+    }
+
+    fn observe(&self, name: &'static str, help: &'static str, labels: &'static [&'static str], values: &[&str], value: f64) {
+        let mut families = self.families.lock().unwrap();
+        let family = Self::family(&mut families, name, help, labels, true);
+        if let Some(Series::Histogram { buckets, sum, count }) = family.series(values) {
+            for (bucket, bound) in buckets.iter_mut().zip(LATENCY_BUCKETS) {
+                if value <= *bound {
+                    *bucket += 1;
+                }
+            }
+            *sum += value;
+            *count += 1;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let _ = writeln!(out, "# HELP {} {}", name, family.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, if family.histogram { "histogram" } else { "counter" });
+            let labels = family.labels;
+            family.series.for_each(&mut Vec::new(), &mut |values, series| match series {
+                Series::Counter(value) => {
+                    let _ = writeln!(out, "{}{} {}", name, format_labels(labels, values, None), value);
+                }
+                Series::Histogram { buckets, sum, count } => {
+                    for (bucket, bound) in buckets.iter().zip(LATENCY_BUCKETS) {
+                        let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, values, Some(("le", bound.to_string()))), bucket);
+                    }
+                    let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(labels, values, Some(("le", "+Inf".into()))), count);
+                    let _ = writeln!(out, "{}_sum{} {}", name, format_labels(labels, values, None), sum);
+                    let _ = writeln!(out, "{}_count{} {}", name, format_labels(labels, values, None), count);
+                }
+            });
+        }
+        out
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        // Write and rename so node_exporter's textfile collector never sees a partial file.
+        let tmp = path.as_ref().with_extension("prom.tmp");
+        std::fs::write(&tmp, self.render())?;
+        std::fs::rename(&tmp, path.as_ref())?;
+        Ok(())
+    }
+
+    /// Rewrites `path` every `interval`.
+    pub fn spawn_file_writer(self: &Arc<Self>, path: PathBuf, interval: Duration) -> JoinHandle<()> {
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(err) = metrics.write_to_file(&path) {
+                    warn!(error = %err, path = %path.display(), "failed to write metrics");
+                }
+            }
+        })
+    }
+
+    /// Serves the metrics over plain HTTP on every path, e.g. `127.0.0.1:9184/metrics`.
+    pub async fn serve<A: ToSocketAddrs>(self: &Arc<Self>, address: A) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        let listener = TcpListener::bind(address).await?;
+        let metrics = self.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        warn!(error = %err, "metrics endpoint accept failed");
+                        continue;
+                    }
+                };
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 1024];
+                    let _ = socket.read(&mut buf).await;
+                    let body = metrics.render();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(), body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        }))
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn request(&self, command: &str) {
+        self.inc("xapi_requests_total", "Requests sent per command.", &["command"], &[command]);
+    }
+
+    fn response(&self, command: &str, latency: Duration) {
+        self.observe("xapi_response_latency_seconds", "Time from request to response per command.",
+            &["command"], &[command], latency.as_secs_f64());
+    }
+
+    fn server_error(&self, error_code: &str) {
+        self.inc("xapi_server_errors_total", "Error responses per xAPI error code.", &["code"], &[error_code]);
+    }
+
+    fn connect(&self, address: &str) {
+        self.inc("xapi_connects_total", "Established connections.", &["address"], &[address]);
+    }
+
+    fn reconnect(&self, address: &str) {
+        self.inc("xapi_reconnects_total", "Reconnections after a lost connection.", &["address"], &[address]);
+    }
+
+    fn stream_message(&self, command: &str, symbol: Option<&str>) {
+        self.inc("xapi_stream_messages_total", "Stream messages per type and symbol.",
+            &["command", "symbol"], &[command, symbol.unwrap_or_default()]);
+    }
+
+    fn unparsable_frame(&self, kind: &str) {
+        self.inc("xapi_unparsable_frames_total", "Frames dropped because they could not be parsed.", &["kind"], &[kind]);
+    }
+
+    fn tick_delay(&self, symbol: &str, delay: Duration) {
+        self.observe("xapi_tick_delay_seconds", "Tick receive time minus tick timestamp.",
+            &["symbol"], &[symbol], delay.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_series_per_label_values() {
+        let metrics = PrometheusMetrics::default();
+        metrics.stream_message("tickPrices", Some("US500"));
+        metrics.stream_message("tickPrices", Some("EURUSD"));
+        metrics.stream_message("tickPrices", Some("EURUSD"));
+        metrics.stream_message("keepAlive", None);
+        metrics.tick_delay("EURUSD", Duration::from_millis(20));
+
+        let rendered = metrics.render();
+        let counters: Vec<&str> = rendered.lines().filter(|line| line.starts_with("xapi_stream_messages_total")).collect();
+        assert_eq!(counters, vec![
+            r#"xapi_stream_messages_total{command="keepAlive",symbol=""} 1"#,
+            r#"xapi_stream_messages_total{command="tickPrices",symbol="EURUSD"} 2"#,
+            r#"xapi_stream_messages_total{command="tickPrices",symbol="US500"} 1"#,
+        ]);
+        assert!(rendered.contains(r#"xapi_tick_delay_seconds_bucket{symbol="EURUSD",le="0.01"} 0"#));
+        assert!(rendered.contains(r#"xapi_tick_delay_seconds_bucket{symbol="EURUSD",le="0.025"} 1"#));
+        assert!(rendered.contains(r#"xapi_tick_delay_seconds_count{symbol="EURUSD"} 1"#));
+    }
+}
@@ -0,0 +1,828 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::config::{ClientConfig, Environment};
+pub use crate::recording::Channel;
+use crate::sim::Quote;
+use crate::transport::ConnectOptions;
+use crate::xapi_definitions::commands_common::RequestStatus;
+use crate::xapi_definitions::commands_main::*;
+use crate::xapi_definitions::commands_stream::{self, GetKeepAliveResponse, GetTradeStatusResponse, RequestStream, ResponseStream};
+
+mod engine;
+mod faults;
+
+use engine::{Engine, PriceFeed};
+use faults::{Faults, FrameWriter};
+pub use engine::{load_quotes, EngineConfig, PriceSource};
+pub use faults::{load_faults, Fault, ScheduledFault, MAX_HOLD};
+
+pub const LOGIN_FAILED: &str = "BE005";
+pub const NOT_LOGGED: &str = "BE103";
+pub const INVALID_SYMBOL: &str = "BE115";
+pub const UNKNOWN_ORDER: &str = "BE007";
+pub const INVALID_REQUEST: &str = "EX000";
+
+const READ_BUF_SIZE: usize = 4096;
+/// How often a stream connection checks for frames held back by `Fault::CoalesceFrames`.
+const HELD_CHECK: Duration = Duration::from_millis(50);
+
+/// Replies of the data commands the mock answers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Fixtures {
+    pub symbols: Vec<SymbolRecord>,
+    pub commission: GetCommissionDefResponse,
+    pub current_user_data: GetCurrentUserDataResponse,
+    /// Order number given to the first trade transaction.
+    pub first_order: u32,
+    /// Status reported for every trade transaction.
+    pub trade_status: RequestStatus,
+}
+
+impl Default for Fixtures {
+    fn default() -> Self {
+        Self {
+            symbols: vec![SymbolRecord {
+                ask: 1.0852,
+                bid: 1.0850,
+                category_name: "FX".into(),
+                contract_size: 100000,
+                currency: "EUR".into(),
+                currency_profit: "USD".into(),
+                description: "Euro to American Dollar".into(),
+                digits: 5,
+                high: 1.0890,
+                leverage: 3.33,
+                lot_max: 100.0,
+                lot_min: 0.01,
+                lot_step: 0.01,
+                low: 1.0810,
+                precision: 5,
+                spread_raw: 0.0002,
+                spread_table: 2.0,
+                symbol: "EURUSD".into(),
+                time: 0,
+                trailing_enabled: true,
+                extra: Default::default(),
+            }],
+            commission: GetCommissionDefResponse { commission: 0.0, rate_of_exchange: 1.0, extra: Default::default() },
+            current_user_data: GetCurrentUserDataResponse {
+                company_unit: 8,
+                currency: "EUR".into(),
+                group: "demoEUR".into(),
+                ib_account: false,
+                leverage: 1,
+                leverage_multiplier: 0.25,
+                spread_type: Some("FLOAT".into()),
+                trailing_stop: false,
+                extra: Default::default(),
+            },
+            first_order: 1000,
+            trade_status: RequestStatus::Accepted,
+        }
+    }
+}
+
+impl Fixtures {
+    /// Reads fixtures from a JSON file, missing keys keep their defaults.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path.as_ref())
+            .map_err(|err| format!("Failed to read fixtures {}: {}", path.as_ref().display(), err))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn symbol(&self, symbol: &str) -> Option<&SymbolRecord> {
+        self.symbols.iter().find(|record| record.symbol == symbol)
+    }
+}
+
+/// Stream message sent `delay_ms` after the previous one of the script.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScriptedMessage {
+    #[serde(default)]
+    pub delay_ms: u64,
+    pub message: ResponseStream,
+}
+
+/// Reads a script from a JSON array of `{"delay_ms": .., "message": {"command": .., "data": ..}}`.
+pub fn load_script<P: AsRef<Path>>(path: P) -> Result<Vec<ScriptedMessage>, Box<dyn Error>> {
+    let content = fs::read_to_string(path.as_ref())
+        .map_err(|err| format!("Failed to read script {}: {}", path.as_ref().display(), err))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+#[derive(Debug, Clone)]
+pub struct MockConfig {
+    /// Accepted logins, user id to password.
+    pub credentials: HashMap<String, String>,
+    pub fixtures: Fixtures,
+    /// Played on every stream connection from its first subscription,
+    /// each message only reaches connections subscribed to it.
+    pub script: Vec<ScriptedMessage>,
+    /// Interval of `keepAlive` messages to subscribed stream connections.
+    pub keep_alive: Option<Duration>,
+    /// TLS with a self-signed certificate for `localhost`, otherwise plain TCP.
+    pub tls: bool,
+    /// Simulated accounts and prices. Without it trade transactions only
+    /// get an order number and `fixtures.trade_status`.
+    pub engine: Option<EngineConfig>,
+    /// Faults applied while running, more can be added with `MockHandle::inject`.
+    pub faults: Vec<ScheduledFault>,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            credentials: HashMap::new(),
+            fixtures: Fixtures::default(),
+            script: Vec::new(),
+            keep_alive: Some(Duration::from_secs(3)),
+            tls: false,
+            engine: None,
+            faults: Vec::new(),
+        }
+    }
+}
+
+impl MockConfig {
+    pub fn user(mut self, user_id: &str, password: &str) -> Self {
+        self.credentials.insert(user_id.into(), password.into());
+        self
+    }
+
+    pub fn engine(mut self, engine: EngineConfig) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Applies `fault` to `channel` from the start.
+    pub fn fault(mut self, channel: Channel, fault: Fault) -> Self {
+        self.faults.push(ScheduledFault { after_ms: 0, channel, fault });
+        self
+    }
+}
+
+/// Stream message for every stream connection, or only those of one login.
+#[derive(Debug, Clone)]
+struct Push {
+    user_id: Option<String>,
+    message: ResponseStream,
+}
+
+struct State {
+    /// Stream session id to user id.
+    sessions: HashMap<String, String>,
+    orders: HashMap<u32, TradeTransactionStatusResponse>,
+    next_order: u32,
+    next_session: u64,
+    engine: Option<Engine>,
+}
+
+struct Shared {
+    config: MockConfig,
+    state: Mutex<State>,
+    faults: Mutex<Faults>,
+    pushes: broadcast::Sender<Push>,
+}
+
+/// Local stand-in for the xAPI main and stream servers.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// use xtb::mock::{MockConfig, MockServer};
+///
+/// let mock = MockServer::new(MockConfig::default().user("1234", "secret"))
+///     .bind("127.0.0.1:0", "127.0.0.1:0").await?;
+/// let config = mock.client_config();
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockServer {
+    shared: Arc<Shared>,
+}
+
+impl MockServer {
+    pub fn new(config: MockConfig) -> Self {
+        let (pushes, _) = broadcast::channel(1024);
+        let state = State {
+            sessions: HashMap::new(),
+            orders: HashMap::new(),
+            next_order: config.fixtures.first_order,
+            next_session: 1,
+            engine: config.engine.clone().map(|engine| Engine::new(engine, &config.fixtures)),
+        };
+        Self {
+            shared: Arc::new(Shared {
+                config,
+                state: Mutex::new(state),
+                faults: Mutex::new(Faults::default()),
+                pushes,
+            }),
+        }
+    }
+
+    /// Starts listening on both addresses, port 0 picks a free port.
+    pub async fn bind<A: ToSocketAddrs, B: ToSocketAddrs>(self, main_address: A, stream_address: B) -> Result<MockHandle, Box<dyn Error>> {
+        let main_listener = TcpListener::bind(main_address).await?;
+        let stream_listener = TcpListener::bind(stream_address).await?;
+        let main_addr = main_listener.local_addr()?;
+        let stream_addr = stream_listener.local_addr()?;
+
+        let (acceptor, certificate_pem) = if self.shared.config.tls {
+            let (acceptor, pem) = self_signed_acceptor()?;
+            (Some(acceptor), Some(pem))
+        } else {
+            (None, None)
+        };
+        let connect_options = match &certificate_pem {
+            Some(pem) => ConnectOptions::new().add_root_certificates_pem(pem.as_bytes())?,
+            None => ConnectOptions::new().plaintext(true),
+        };
+
+        info!(main = %main_addr, stream = %stream_addr, tls = self.shared.config.tls, "mock xAPI listening");
+        let mut tasks = vec![
+            accept(main_listener, acceptor.clone(), self.shared.clone(), Channel::Main),
+            accept(stream_listener, acceptor, self.shared.clone(), Channel::Stream),
+        ];
+        let mut scheduled = self.shared.config.faults.clone();
+        scheduled.sort_by_key(|scheduled| scheduled.after_ms);
+        let (immediate, later): (Vec<_>, Vec<_>) = scheduled.into_iter().partition(|scheduled| scheduled.after_ms == 0);
+        for scheduled in immediate {
+            self.shared.faults.lock().unwrap().inject(scheduled.channel, scheduled.fault);
+        }
+        if !later.is_empty() {
+            tasks.push(schedule_faults(later, self.shared.clone()));
+        }
+        if let Some(engine) = &self.shared.config.engine {
+            let feed = PriceFeed::new(engine.prices.clone(), &self.shared.config.fixtures);
+            if let Some(interval) = feed.interval() {
+                tasks.push(feed_prices(feed, interval, self.shared.clone()));
+            }
+        }
+
+        Ok(MockHandle {
+            main_addr,
+            stream_addr,
+            certificate_pem,
+            connect_options,
+            shared: self.shared,
+            tasks,
+        })
+    }
+}
+
+/// Running mock server, stops listening when dropped.
+pub struct MockHandle {
+    main_addr: SocketAddr,
+    stream_addr: SocketAddr,
+    certificate_pem: Option<String>,
+    connect_options: ConnectOptions,
+    shared: Arc<Shared>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl MockHandle {
+    pub fn main_addr(&self) -> SocketAddr {
+        self.main_addr
+    }
+
+    pub fn stream_addr(&self) -> SocketAddr {
+        self.stream_addr
+    }
+
+    /// PEM of the self-signed certificate, `None` on plain TCP.
+    pub fn certificate_pem(&self) -> Option<&str> {
+        self.certificate_pem.as_deref()
+    }
+
+    /// Plain TCP, or with TLS trusting only the self-signed certificate of this server.
+    /// Pass them to `XApiClient::connect_options`, a `ClientConfig` cannot hold the certificate.
+    pub fn connect_options(&self) -> ConnectOptions {
+        self.connect_options.clone()
+    }
+
+    /// Client settings pointing at this server.
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig {
+            profile: "mock".into(),
+            environment: Environment::Custom,
+            host: self.main_addr.ip().to_string(),
+            port: self.main_addr.port(),
+            stream_port: self.stream_addr.port(),
+            plaintext: !self.shared.config.tls,
+            ..ClientConfig::default()
+        }
+    }
+
+    /// Sends `message` to every subscribed stream connection.
+    pub fn push(&self, message: ResponseStream) {
+        let _ = self.shared.pushes.send(Push { user_id: None, message });
+    }
+
+    /// Sends `message` to the subscribed stream connections of `user_id`.
+    pub fn push_to(&self, user_id: &str, message: ResponseStream) {
+        let _ = self.shared.pushes.send(Push { user_id: Some(user_id.into()), message });
+    }
+
+    /// Applies `fault` to connections of `channel`, see `Fault` for how long it lasts.
+    pub fn inject(&self, channel: Channel, fault: Fault) {
+        self.shared.faults.lock().unwrap().inject(channel, fault);
+    }
+
+    /// Removes all faults, the server behaves normally again.
+    pub fn clear_faults(&self) {
+        self.shared.faults.lock().unwrap().clear();
+    }
+
+    /// Feeds a quote to the engine and to `getTickPrices` subscribers.
+    pub fn quote(&self, quote: Quote) {
+        self.shared.quote(quote);
+    }
+
+    pub fn shutdown(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+
+    /// Runs until the listeners fail or the task is cancelled.
+    pub async fn wait(mut self) {
+        for task in self.tasks.drain(..) {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for MockHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl State {
+    /// Trade transaction without an engine: a new order number and the fixture status.
+    fn fixture_trade(&mut self, fixtures: &Fixtures, user_id: &str, info: TradeTransInfo) -> (u32, Vec<Push>) {
+        let (ask, bid) = fixtures.symbol(&info.symbol)
+            .map(|symbol| (symbol.ask, symbol.bid))
+            .unwrap_or_default();
+        let order = self.next_order;
+        self.next_order += 1;
+        self.orders.insert(order, TradeTransactionStatusResponse {
+            ask,
+            bid,
+            custom_comment: info.custom_comment.clone(),
+            message: None,
+            order,
+            request_status: fixtures.trade_status,
+            extra: Default::default(),
+        });
+
+        let push = Push {
+            user_id: Some(user_id.into()),
+            message: ResponseStream::TradeStatus(commands_stream::GetResponse {
+                data: GetTradeStatusResponse {
+                    custom_comment: info.custom_comment,
+                    message: None,
+                    order,
+                    price: if info.price != 0.0 { info.price } else { ask },
+                    request_status: fixtures.trade_status,
+                    extra: Default::default(),
+                },
+                extra: Default::default(),
+            }),
+        };
+        (order, vec![push])
+    }
+}
+
+impl Shared {
+    fn send(&self, pushes: Vec<Push>) {
+        for push in pushes {
+            let _ = self.pushes.send(push);
+        }
+    }
+
+    fn quote(&self, quote: Quote) {
+        let pushes = match &mut self.state.lock().unwrap().engine {
+            Some(engine) => engine.on_quote(quote),
+            None => vec![Push { user_id: None, message: engine::tick_message(&quote) }],
+        };
+        self.send(pushes);
+    }
+}
+
+fn schedule_faults(scheduled: Vec<ScheduledFault>, shared: Arc<Shared>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let start = tokio::time::Instant::now();
+        for scheduled in scheduled {
+            tokio::time::sleep_until(start + Duration::from_millis(scheduled.after_ms)).await;
+            info!(channel = ?scheduled.channel, fault = ?scheduled.fault, "mock fault injected");
+            shared.faults.lock().unwrap().inject(scheduled.channel, scheduled.fault);
+        }
+    })
+}
+
+fn feed_prices(mut feed: PriceFeed, interval: Duration, shared: Arc<Shared>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            let quotes = feed.next_quotes();
+            if quotes.is_empty() {
+                info!("mock price feed finished");
+                return;
+            }
+            for quote in quotes {
+                shared.quote(quote);
+            }
+        }
+    })
+}
+
+fn self_signed_acceptor() -> Result<(TlsAcceptor, String), Box<dyn Error>> {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string(), "127.0.0.1".to_string()])?;
+    let key = pki_types::PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into());
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![certified.cert.der().clone()], key)?;
+    Ok((TlsAcceptor::from(Arc::new(config)), certified.cert.pem()))
+}
+
+fn accept(listener: TcpListener, acceptor: Option<TlsAcceptor>, shared: Arc<Shared>, channel: Channel) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let (socket, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(error = %err, "mock failed to accept connection");
+                    continue;
+                }
+            };
+            let shared = shared.clone();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                debug!(%peer, ?channel, "mock connection");
+                let result = match acceptor {
+                    Some(acceptor) => match acceptor.accept(socket).await {
+                        Ok(socket) => serve(socket, shared, channel).await,
+                        Err(err) => Err(err.into()),
+                    },
+                    None => serve::<TcpStream>(socket, shared, channel).await,
+                };
+                if let Err(err) = result {
+                    debug!(%peer, error = %err, "mock connection ended");
+                }
+            });
+        }
+    })
+}
+
+async fn serve<S>(socket: S, shared: Arc<Shared>, channel: Channel) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    match channel {
+        Channel::Main => MainConnection { shared, user_id: None }.run(socket).await,
+        Channel::Stream => StreamConnection::run(shared, socket).await,
+    }
+}
+
+/// Requests are sent without a terminator, so complete JSON values are cut off the
+/// front of `buffer` and an incomplete tail is kept for the next read.
+fn take_requests(buffer: &mut Vec<u8>) -> Result<Vec<Value>, serde_json::Error> {
+    let mut requests = Vec::new();
+    let mut values = serde_json::Deserializer::from_slice(buffer).into_iter::<Value>();
+    let consumed = loop {
+        let offset = values.byte_offset();
+        match values.next() {
+            Some(Ok(value)) => requests.push(value),
+            Some(Err(err)) if err.is_eof() => break offset,
+            Some(Err(err)) => return Err(err),
+            None => break values.byte_offset(),
+        }
+    };
+    buffer.drain(..consumed);
+    Ok(requests)
+}
+
+async fn read_requests<R: AsyncRead + Unpin>(socket: &mut R, buffer: &mut Vec<u8>) -> Result<Option<Vec<Value>>, Box<dyn Error + Send + Sync>> {
+    loop {
+        match take_requests(buffer) {
+            Ok(requests) if !requests.is_empty() => return Ok(Some(requests)),
+            Ok(_) => {}
+            Err(err) => {
+                buffer.clear();
+                return Err(format!("Malformed request: {}", err).into());
+            }
+        }
+        let mut buf = vec![0; READ_BUF_SIZE];
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&buf[..n]);
+    }
+}
+
+fn error_reply(code: &str, description: &str, custom_tag: Option<String>) -> Value {
+    serde_json::to_value(ErrorResponse {
+        status: false,
+        error_code: code.into(),
+        error_descr: description.into(),
+        custom_tag,
+        extra: Default::default(),
+    }).unwrap_or_default()
+}
+
+fn data_reply<T: Serialize>(return_data: T, custom_tag: Option<String>) -> Value {
+    serde_json::to_value(GetResponse { status: true, return_data, custom_tag, extra: Default::default() }).unwrap_or_default()
+}
+
+struct MainConnection {
+    shared: Arc<Shared>,
+    user_id: Option<String>,
+}
+
+impl MainConnection {
+    async fn run<S: AsyncRead + AsyncWrite + Unpin>(mut self, mut socket: S) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let shared = self.shared.clone();
+        let mut writer = FrameWriter::new(Channel::Main);
+        let mut buffer = Vec::new();
+        loop {
+            let requests = match read_requests(&mut socket, &mut buffer).await {
+                Ok(Some(requests)) => requests,
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    writer.send(&mut socket, &shared, &error_reply(INVALID_REQUEST, &err.to_string(), None)).await?;
+                    writer.flush_held(&mut socket, &shared).await?;
+                    continue;
+                }
+            };
+            for request in requests {
+                if request["command"] == "logout" {
+                    writer.send(&mut socket, &shared, &json!({ "status": true })).await?;
+                    writer.flush_held(&mut socket, &shared).await?;
+                    return Ok(());
+                }
+                let injected = shared.faults.lock().unwrap().take_error(request["command"].as_str().unwrap_or_default());
+                let reply = match injected {
+                    Some((code, description)) => {
+                        error_reply(&code, &description, request["customTag"].as_str().map(String::from))
+                    }
+                    None => self.reply(request),
+                };
+                writer.send(&mut socket, &shared, &reply).await?;
+            }
+            writer.flush_held(&mut socket, &shared).await?;
+        }
+    }
+
+    fn reply(&mut self, request: Value) -> Value {
+        let custom_tag = request["customTag"].as_str().map(String::from);
+        let request = match serde_json::from_value::<Request>(request) {
+            Ok(request) => request,
+            Err(err) => return error_reply(INVALID_REQUEST, &format!("Invalid request: {}", err), custom_tag),
+        };
+        if let Request::Login(login) = &request {
+            return self.login(login, custom_tag);
+        }
+        let Some(user_id) = self.user_id.clone() else {
+            return error_reply(NOT_LOGGED, "User is not logged", custom_tag);
+        };
+
+        let shared = self.shared.clone();
+        let fixtures = &shared.config.fixtures;
+        let mut guard = shared.state.lock().unwrap();
+        let state = &mut *guard;
+        match (request, state.engine.as_mut()) {
+            (Request::GetAllSymbols(_), Some(engine)) => data_reply(engine.symbols(), custom_tag),
+            (Request::GetAllSymbols(_), None) => data_reply(&fixtures.symbols, custom_tag),
+            (Request::GetSymbol(GetSymbol { symbol }), engine) => {
+                let record = match engine {
+                    Some(engine) => engine.symbol(&symbol),
+                    None => fixtures.symbol(&symbol).cloned(),
+                };
+                match record {
+                    Some(record) => data_reply(record, custom_tag),
+                    None => error_reply(INVALID_SYMBOL, &format!("Symbol {} does not exist", symbol), custom_tag),
+                }
+            }
+            (Request::GetCommissionDef(GetCommissionDef { symbol, .. }), _) => match fixtures.symbol(&symbol) {
+                Some(_) => data_reply(&fixtures.commission, custom_tag),
+                None => error_reply(INVALID_SYMBOL, &format!("Symbol {} does not exist", symbol), custom_tag),
+            },
+            (Request::GetCurrentUserData(_), _) => data_reply(&fixtures.current_user_data, custom_tag),
+            (Request::GetMarginLevel(_), Some(engine)) => data_reply(engine.margin_level(&user_id), custom_tag),
+            (Request::GetMarginTrade(GetMarginTradeRequest { symbol, volume }), Some(engine)) => {
+                match engine.margin_trade(&user_id, &symbol, volume) {
+                    Some(margin) => data_reply(margin, custom_tag),
+                    None => error_reply(INVALID_SYMBOL, &format!("Symbol {} does not exist", symbol), custom_tag),
+                }
+            }
+            (Request::GetTrades(GetTradesRequest { opened_only }), Some(engine)) => {
+                data_reply(engine.trades(&user_id, opened_only), custom_tag)
+            }
+            (Request::GetTradesHistory(GetTradesHistoryRequest { end, start }), Some(engine)) => {
+                data_reply(engine.trades_history(&user_id, start, end), custom_tag)
+            }
+            (Request::TradeTransaction(TradeTransaction { trade_trans_info }), engine) => {
+                if fixtures.symbol(&trade_trans_info.symbol).is_none() {
+                    let error = format!("Symbol {} does not exist", trade_trans_info.symbol);
+                    return error_reply(INVALID_SYMBOL, &error, custom_tag);
+                }
+                let (order, pushes) = match engine {
+                    Some(engine) => engine.trade_transaction(&user_id, &trade_trans_info),
+                    None => state.fixture_trade(fixtures, &user_id, trade_trans_info),
+                };
+                shared.send(pushes);
+                data_reply(TradeTransactionResponse { order, extra: Default::default() }, custom_tag)
+            }
+            (Request::TradeTransactionStatus(TradeTransactionStatus { order }), engine) => {
+                let status = match engine {
+                    Some(engine) => engine.trade_status(&user_id, order),
+                    None => state.orders.get(&order).cloned(),
+                };
+                match status {
+                    Some(status) => data_reply(status, custom_tag),
+                    None => error_reply(UNKNOWN_ORDER, &format!("Order {} not found", order), custom_tag),
+                }
+            }
+            _ => error_reply(INVALID_REQUEST, "Command not supported by the mock", custom_tag),
+        }
+    }
+
+    fn login(&mut self, login: &LoginRequest, custom_tag: Option<String>) -> Value {
+        let accepted = self.shared.config.credentials.get(&login.user_id)
+            .is_some_and(|password| password == login.password.expose());
+        if !accepted {
+            info!(user_id = %login.user_id, "mock login refused");
+            return error_reply(LOGIN_FAILED, "userPasswordCheck: Invalid login or password", custom_tag);
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        let stream_session_id = format!("mock-{}-{}", login.user_id, state.next_session);
+        state.next_session += 1;
+        state.sessions.insert(stream_session_id.clone(), login.user_id.clone());
+        if let Some(engine) = &mut state.engine {
+            engine.login(&login.user_id);
+        }
+        self.user_id = Some(login.user_id.clone());
+        info!(user_id = %login.user_id, "mock login");
+
+        serde_json::to_value(LoginResponse {
+            status: true,
+            stream_session_id: stream_session_id.into(),
+            custom_tag,
+            extra: Default::default(),
+        }).unwrap_or_default()
+    }
+}
+
+/// Subscription key, the command and the symbol for per-symbol streams.
+type Subscription = (String, Option<String>);
+
+/// `None` for commands the client does not know, which are sent to every stream connection.
+fn subscription_of(message: &ResponseStream) -> Option<Subscription> {
+    let command = match message {
+        ResponseStream::Candle(_) => "getCandles",
+        ResponseStream::Balance(_) => "getBalance",
+        ResponseStream::KeepAlive(_) => "getKeepAlive",
+        ResponseStream::Profit(_) => "getProfits",
+        ResponseStream::TickPrices(_) => "getTickPrices",
+        ResponseStream::Trade(_) => "getTrades",
+        ResponseStream::TradeStatus(_) => "getTradeStatus",
+        ResponseStream::Unknown(_) => return None,
+    };
+    let symbol = match message {
+        ResponseStream::Candle(candle) => Some(candle.data.symbol.clone()),
+        ResponseStream::TickPrices(tick) => Some(tick.data.symbol.clone()),
+        _ => None,
+    };
+    Some((command.into(), symbol))
+}
+
+struct StreamConnection;
+
+impl StreamConnection {
+    async fn run<S>(shared: Arc<Shared>, socket: S) -> Result<(), Box<dyn Error + Send + Sync>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut reader, mut socket) = tokio::io::split(socket);
+        let mut writer = FrameWriter::new(Channel::Stream);
+        let mut pushes = shared.pushes.subscribe();
+        let (script_sender, mut script) = mpsc::unbounded_channel::<ResponseStream>();
+        let mut script_sender = Some(script_sender);
+        let mut keep_alive = shared.config.keep_alive.map(tokio::time::interval);
+        let mut held_check = tokio::time::interval(HELD_CHECK);
+        let mut subscriptions: HashSet<Subscription> = HashSet::new();
+        let mut user_id: Option<String> = None;
+        let mut buffer = Vec::new();
+        let mut script_task: Option<JoinHandle<()>> = None;
+
+        let result = loop {
+            let message = tokio::select! {
+                requests = read_requests(&mut reader, &mut buffer) => {
+                    let requests = match requests {
+                        Ok(Some(requests)) => requests,
+                        Ok(None) => break Ok(()),
+                        Err(err) => break Err(err),
+                    };
+                    for request in requests {
+                        let session = request["streamSessionId"].as_str()
+                            .and_then(|id| shared.state.lock().unwrap().sessions.get(id).cloned());
+                        let Some(session_user) = session else {
+                            let _ = writer.send(&mut socket, &shared, &error_reply(NOT_LOGGED, "Invalid stream session id", None)).await;
+                            let _ = writer.flush_held(&mut socket, &shared).await;
+                            return Ok(());
+                        };
+                        user_id = Some(session_user);
+
+                        let command = request["command"].as_str().unwrap_or_default().to_string();
+                        let symbol = request["symbol"].as_str().map(String::from);
+                        if let Some(stopped) = command.strip_prefix("stop") {
+                            subscriptions.remove(&(format!("get{}", stopped), symbol));
+                            continue;
+                        }
+                        if let Err(err) = serde_json::from_value::<RequestStream>(request) {
+                            warn!(error = %err, "mock received invalid subscription");
+                            continue;
+                        }
+                        subscriptions.insert((command, symbol));
+                        if let Some(sender) = script_sender.take() {
+                            script_task = Some(play_script(shared.config.script.clone(), sender));
+                        }
+                    }
+                    continue;
+                }
+                Some(message) = script.recv() => message,
+                push = pushes.recv() => match push {
+                    Ok(push) if push.user_id.is_none() || push.user_id == user_id => push.message,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "mock stream connection lagging behind pushes");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break Ok(()),
+                },
+                _ = held_check.tick(), if writer.is_holding() => {
+                    if let Err(err) = writer.flush_stale(&mut socket, &shared).await {
+                        break Err(err);
+                    }
+                    continue;
+                }
+                _ = async { keep_alive.as_mut().unwrap().tick().await }, if keep_alive.is_some() => {
+                    if shared.faults.lock().unwrap().keep_alive_stopped() {
+                        continue;
+                    }
+                    ResponseStream::KeepAlive(commands_stream::GetResponse {
+                        data: GetKeepAliveResponse { timestamp: chrono::Utc::now().timestamp_millis(), extra: Default::default() },
+                        extra: Default::default(),
+                    })
+                }
+            };
+
+            if subscription_of(&message).is_some_and(|subscription| !subscriptions.contains(&subscription)) {
+                continue;
+            }
+            let message = match serde_json::to_value(&message) {
+                Ok(message) => message,
+                Err(err) => break Err(err.into()),
+            };
+            if let Err(err) = writer.send(&mut socket, &shared, &message).await {
+                break Err(err);
+            }
+        };
+
+        if let Some(task) = script_task {
+            task.abort();
+        }
+        result
+    }
+}
+
+fn play_script(script: Vec<ScriptedMessage>, sender: mpsc::UnboundedSender<ResponseStream>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        for scripted in script {
+            tokio::time::sleep(Duration::from_millis(scripted.delay_ms)).await;
+            if sender.send(scripted.message).is_err() {
+                return;
+            }
+        }
+    })
+}
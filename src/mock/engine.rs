@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::sim::{ClosedTrade, Quote, SimOrder, SimulatedAccount};
+use crate::xapi_definitions::commands_common::{State, Type};
+use crate::xapi_definitions::commands_main::*;
+use crate::xapi_definitions::commands_stream::{self, GetTickPricesResponse, ResponseStream};
+
+use super::{Fixtures, Push};
+
+/// Where the engine takes its prices from.
+#[derive(Debug, Clone)]
+pub enum PriceSource {
+    /// Recorded quotes, one every `interval`.
+    Replay { quotes: Vec<Quote>, interval: Duration },
+    /// Every fixture symbol moves by up to `step` (relative) every `interval`,
+    /// the same `seed` gives the same prices.
+    RandomWalk { step: f64, interval: Duration, seed: u64 },
+    /// Only quotes passed to `MockHandle::quote`.
+    Manual,
+}
+
+/// Reads quotes for `PriceSource::Replay`, one JSON object per line:
+/// `{"symbol": "EURUSD", "bid": 1.085, "ask": 1.0852, "time": 1700000000000}`.
+pub fn load_quotes<P: AsRef<Path>>(path: P) -> Result<Vec<Quote>, Box<dyn Error>> {
+    let content = fs::read_to_string(path.as_ref())
+        .map_err(|err| format!("Failed to read quotes {}: {}", path.as_ref().display(), err))?;
+    content.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Simulated accounts behind the mock, one per login. Contract size and leverage
+/// come from the `SymbolRecord` of each fixture symbol.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Starting balance of every account.
+    pub balance: f64,
+    pub prices: PriceSource,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            balance: 10_000.0,
+            prices: PriceSource::RandomWalk { step: 0.0001, interval: Duration::from_millis(500), seed: 1 },
+        }
+    }
+}
+
+/// Produces the quotes of a `PriceSource`.
+pub struct PriceFeed {
+    source: PriceSource,
+    next_quote: usize,
+    rng: u64,
+    prices: Vec<(String, f64, f64, u16)>,
+}
+
+impl PriceFeed {
+    pub fn new(source: PriceSource, fixtures: &Fixtures) -> Self {
+        let rng = match &source {
+            PriceSource::RandomWalk { seed, .. } => (*seed).max(1),
+            _ => 1,
+        };
+        let prices = fixtures.symbols.iter()
+            .map(|symbol| (symbol.symbol.clone(), symbol.bid as f64, (symbol.ask - symbol.bid) as f64, symbol.digits))
+            .collect();
+        Self { source, next_quote: 0, rng, prices }
+    }
+
+    /// Time between quotes, `None` when nothing is generated.
+    pub fn interval(&self) -> Option<Duration> {
+        match &self.source {
+            PriceSource::Replay { interval, .. } | PriceSource::RandomWalk { interval, .. } => Some(*interval),
+            PriceSource::Manual => None,
+        }
+    }
+
+    /// Quotes of the next step, empty once a replay has ended.
+    pub fn next_quotes(&mut self) -> Vec<Quote> {
+        match &self.source {
+            PriceSource::Replay { quotes, .. } => {
+                let quote = quotes.get(self.next_quote).cloned();
+                self.next_quote += 1;
+                quote.into_iter().collect()
+            }
+            PriceSource::RandomWalk { step, .. } => {
+                let step = *step;
+                let time = chrono::Utc::now().timestamp_millis();
+                let mut quotes = Vec::with_capacity(self.prices.len());
+                for index in 0..self.prices.len() {
+                    let shift = self.random() * 2.0 - 1.0;
+                    let (symbol, bid, spread, digits) = &mut self.prices[index];
+                    let scale = 10f64.powi(*digits as i32);
+                    *bid = (*bid * (1.0 + step * shift) * scale).round() / scale;
+                    let ask = ((*bid + *spread) * scale).round() / scale;
+                    quotes.push(Quote { symbol: symbol.clone(), bid: *bid, ask, time });
+                }
+                quotes
+            }
+            PriceSource::Manual => Vec::new(),
+        }
+    }
+
+    /// xorshift64*, uniform in [0, 1).
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub fn tick_message(quote: &Quote) -> ResponseStream {
+    ResponseStream::TickPrices(commands_stream::GetResponse {
+        data: GetTickPricesResponse {
+            ask: quote.ask as f32,
+            ask_volume: 0,
+            bid: quote.bid as f32,
+            bid_volume: 0,
+            high: quote.ask as f32,
+            level: 0,
+            low: quote.bid as f32,
+            quote_id: 0,
+            spread_raw: (quote.ask - quote.bid) as f32,
+            spread_table: (quote.ask - quote.bid) as f32,
+            symbol: quote.symbol.clone(),
+            timestamp: quote.time,
+            extra: Default::default(),
+        },
+        extra: Default::default(),
+    })
+}
+
+fn open_record(account: &SimulatedAccount, order: &SimOrder, digits: u16) -> TradeRecord {
+    let trade = account.trade_response(order, Type::Open, State::Modified);
+    TradeRecord {
+        close_price: trade.close_price,
+        close_time: None,
+        closed: false,
+        cmd: order.cmd,
+        comment: None,
+        commission: Some(order.commission as f32),
+        custom_comment: order.custom_comment.clone(),
+        digits,
+        expiration: (order.expiration > 0).then_some(order.expiration),
+        margin_rate: 0.0,
+        offset: 0,
+        open_price: order.price as f32,
+        open_time: order.open_time,
+        order: order.order,
+        order2: order.order,
+        position: order.order,
+        profit: trade.profit,
+        sl: order.sl as f32,
+        storage: 0.0,
+        symbol: Some(order.symbol.clone()),
+        tp: order.tp as f32,
+        volume: order.volume as f32,
+        extra: Default::default(),
+    }
+}
+
+fn closed_record(closed: &ClosedTrade, digits: u16) -> TradeRecord {
+    TradeRecord {
+        close_price: closed.close_price as f32,
+        close_time: Some(closed.close_time),
+        closed: true,
+        cmd: closed.cmd,
+        comment: None,
+        commission: Some(closed.commission as f32),
+        custom_comment: None,
+        digits,
+        expiration: None,
+        margin_rate: 0.0,
+        offset: 0,
+        open_price: closed.open_price as f32,
+        open_time: closed.open_time,
+        order: closed.position,
+        order2: closed.position,
+        position: closed.position,
+        profit: Some(closed.profit as f32),
+        sl: 0.0,
+        storage: 0.0,
+        symbol: Some(closed.symbol.clone()),
+        tp: 0.0,
+        volume: closed.volume as f32,
+        extra: Default::default(),
+    }
+}
+
+/// Order matching of the mock. Every login gets a `SimulatedAccount` fed with the
+/// same quotes, and the stream messages it produces go to that login only.
+pub struct Engine {
+    config: EngineConfig,
+    fixtures: Fixtures,
+    quotes: HashMap<String, Quote>,
+    accounts: HashMap<String, SimulatedAccount>,
+    statuses: HashMap<(String, u32), TradeTransactionStatusResponse>,
+}
+
+impl Engine {
+    pub fn new(config: EngineConfig, fixtures: &Fixtures) -> Self {
+        let quotes = fixtures.symbols.iter()
+            .map(|symbol| (symbol.symbol.clone(), Quote {
+                symbol: symbol.symbol.clone(),
+                bid: symbol.bid as f64,
+                ask: symbol.ask as f64,
+                time: symbol.time,
+            }))
+            .collect();
+        Self {
+            config,
+            fixtures: fixtures.clone(),
+            quotes,
+            accounts: HashMap::new(),
+            statuses: HashMap::new(),
+        }
+    }
+
+    fn digits(&self, symbol: &str) -> u16 {
+        self.fixtures.symbol(symbol).map(|record| record.digits).unwrap_or(5)
+    }
+
+    fn account(&mut self, user_id: &str) -> &mut SimulatedAccount {
+        if !self.accounts.contains_key(user_id) {
+            let mut account = SimulatedAccount::new(self.config.balance).first_order(self.fixtures.first_order);
+            for record in &self.fixtures.symbols {
+                // `SymbolRecord::leverage` is the margin requirement in percent, 3.33 is 1:30.
+                let leverage = if record.leverage > 0.0 { 100.0 / record.leverage as f64 } else { 1.0 };
+                account = account.symbol_spec(&record.symbol, record.contract_size as f64, leverage);
+            }
+            for quote in self.quotes.values() {
+                account.on_quote(quote.clone());
+            }
+            self.accounts.insert(user_id.into(), account);
+        }
+        self.accounts.get_mut(user_id).unwrap()
+    }
+
+    /// Opens the account of `user_id` if it has none yet.
+    pub fn login(&mut self, user_id: &str) {
+        self.account(user_id);
+    }
+
+    /// Fixture symbol with the current price.
+    pub fn symbol(&self, symbol: &str) -> Option<SymbolRecord> {
+        let mut record = self.fixtures.symbol(symbol)?.clone();
+        if let Some(quote) = self.quotes.get(symbol) {
+            record.bid = quote.bid as f32;
+            record.ask = quote.ask as f32;
+            record.time = quote.time;
+        }
+        Some(record)
+    }
+
+    pub fn symbols(&self) -> Vec<SymbolRecord> {
+        self.fixtures.symbols.iter().filter_map(|record| self.symbol(&record.symbol)).collect()
+    }
+
+    fn record_statuses(&mut self, user_id: &str, events: &[ResponseStream], symbol: &str) {
+        let quote = self.quotes.get(symbol);
+        for event in events {
+            if let ResponseStream::TradeStatus(status) = event {
+                self.statuses.insert((user_id.into(), status.data.order), TradeTransactionStatusResponse {
+                    ask: quote.map(|quote| quote.ask as f32).unwrap_or_default(),
+                    bid: quote.map(|quote| quote.bid as f32).unwrap_or_default(),
+                    custom_comment: status.data.custom_comment.clone(),
+                    message: status.data.message.clone(),
+                    order: status.data.order,
+                    request_status: status.data.request_status,
+                    extra: Default::default(),
+                });
+            }
+        }
+    }
+
+    fn pushes(user_id: &str, events: Vec<ResponseStream>) -> impl Iterator<Item = Push> + '_ {
+        events.into_iter().map(move |message| Push { user_id: Some(user_id.into()), message })
+    }
+
+    /// Applies a new price to every account: pending orders, SL/TP, profits and balance.
+    pub fn on_quote(&mut self, quote: Quote) -> Vec<Push> {
+        self.quotes.insert(quote.symbol.clone(), quote.clone());
+        let mut pushes = vec![Push { user_id: None, message: tick_message(&quote) }];
+
+        let user_ids: Vec<String> = self.accounts.keys().cloned().collect();
+        for user_id in user_ids {
+            let account = self.accounts.get_mut(&user_id).unwrap();
+            let mut events = account.on_quote(quote.clone());
+            let open: Vec<&SimOrder> = account.positions().iter()
+                .filter(|position| position.symbol == quote.symbol)
+                .collect();
+            if !open.is_empty() {
+                events.extend(open.iter().map(|position| ResponseStream::Profit(commands_stream::GetResponse {
+                    data: account.profit_response(position),
+                    extra: Default::default(),
+                })));
+                if !events.iter().any(|event| matches!(event, ResponseStream::Balance(_))) {
+                    events.push(ResponseStream::Balance(commands_stream::GetResponse { data: account.balance_response(), extra: Default::default() }));
+                }
+            }
+            self.record_statuses(&user_id, &events, &quote.symbol);
+            pushes.extend(Self::pushes(&user_id, events));
+        }
+        pushes
+    }
+
+    /// Executes a trade request for `user_id`, returning the order number and the stream messages.
+    pub fn trade_transaction(&mut self, user_id: &str, info: &TradeTransInfo) -> (u32, Vec<Push>) {
+        let time = self.quotes.get(&info.symbol)
+            .map(|quote| quote.time)
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+        let execution = self.account(user_id).submit(info, time);
+        self.record_statuses(user_id, &execution.events, &info.symbol);
+        (execution.order, Self::pushes(user_id, execution.events).collect())
+    }
+
+    pub fn trade_status(&self, user_id: &str, order: u32) -> Option<TradeTransactionStatusResponse> {
+        self.statuses.get(&(user_id.into(), order)).cloned()
+    }
+
+    /// Open positions and pending orders, with closed trades unless `opened_only`.
+    pub fn trades(&mut self, user_id: &str, opened_only: bool) -> Vec<TradeRecord> {
+        self.account(user_id);
+        let account = &self.accounts[user_id];
+        let mut trades: Vec<TradeRecord> = account.positions().iter().chain(account.pending())
+            .map(|order| open_record(account, order, self.digits(&order.symbol)))
+            .collect();
+        if !opened_only {
+            trades.extend(account.closed().iter().map(|closed| closed_record(closed, self.digits(&closed.symbol))));
+        }
+        trades
+    }
+
+    /// Trades closed between `start` and `end`, `end` 0 means now.
+    pub fn trades_history(&mut self, user_id: &str, start: i64, end: i64) -> Vec<TradeRecord> {
+        self.account(user_id);
+        let account = &self.accounts[user_id];
+        account.closed().iter()
+            .filter(|closed| closed.close_time >= start && (end == 0 || closed.close_time <= end))
+            .map(|closed| closed_record(closed, self.digits(&closed.symbol)))
+            .collect()
+    }
+
+    pub fn margin_level(&mut self, user_id: &str) -> GetMarginLevelResponse {
+        let currency = self.fixtures.current_user_data.currency.clone();
+        let balance = self.account(user_id).balance_response();
+        GetMarginLevelResponse {
+            balance: balance.balance,
+            credit: balance.credit,
+            currency,
+            equity: balance.equity,
+            margin: balance.margin,
+            margin_free: balance.margin_free,
+            margin_level: balance.margin_level,
+            extra: Default::default(),
+        }
+    }
+
+    pub fn margin_trade(&mut self, user_id: &str, symbol: &str, volume: f32) -> Option<GetMarginTradeResponse> {
+        self.account(user_id).margin_for(symbol, volume as f64)
+            .map(|margin| GetMarginTradeResponse { margin: margin as f32, extra: Default::default() })
+    }
+
+}
@@ -0,0 +1,229 @@
+use std::error::Error;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::FRAME_TERMINATOR;
+
+use super::{Channel, Shared};
+
+const DEFAULT_MALFORMED: &str = "{\"status\":tru";
+/// Longest time `CoalesceFrames` holds a frame back on the stream connection.
+pub const MAX_HOLD: Duration = Duration::from_millis(500);
+
+/// Misbehaviour of the mock. `Error`, `Malformed` and `DropMidFrame` hit the next
+/// frame of one connection and are used up, the others last until cleared.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "fault", rename_all = "snake_case")]
+pub enum Fault {
+    /// Writes every frame in pieces of `chunk` bytes, `pause_ms` apart.
+    SplitFrames { chunk: usize, #[serde(default)] pause_ms: u64 },
+    /// Holds frames back and writes `frames` of them at once. Main replies are
+    /// also written when every request of a read has been answered, stream messages
+    /// after `MAX_HOLD` or as soon as the fault is cleared.
+    CoalesceFrames { frames: usize },
+    /// Waits before writing every frame.
+    Delay { ms: u64 },
+    /// Closes the connection after `bytes` of the next frame, half of it by default.
+    DropMidFrame { #[serde(default)] bytes: Option<usize> },
+    /// Answers the next request, or the next `command`, with an `ErrorResponse`.
+    /// Main connection only.
+    Error {
+        #[serde(default)]
+        command: Option<String>,
+        code: String,
+        #[serde(default)]
+        description: String,
+    },
+    /// Sends `text` instead of the next frame.
+    Malformed { #[serde(default)] text: Option<String> },
+    /// No more `keepAlive` messages. Stream connection only.
+    StopKeepAlive,
+}
+
+impl Fault {
+    fn is_one_shot(&self) -> bool {
+        matches!(self, Fault::Error { .. } | Fault::Malformed { .. } | Fault::DropMidFrame { .. })
+    }
+}
+
+/// Fault applied `after_ms` after the server started.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScheduledFault {
+    #[serde(default)]
+    pub after_ms: u64,
+    pub channel: Channel,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+/// Reads scheduled faults from a JSON array such as
+/// `[{"after_ms": 5000, "channel": "main", "fault": "delay", "ms": 2000}]`.
+pub fn load_faults<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<ScheduledFault>, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path.as_ref())
+        .map_err(|err| format!("Failed to read faults {}: {}", path.as_ref().display(), err))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Faults currently in effect.
+#[derive(Debug, Default)]
+pub struct Faults {
+    active: Vec<(Channel, Fault)>,
+}
+
+impl Faults {
+    pub fn inject(&mut self, channel: Channel, fault: Fault) {
+        self.active.push((channel, fault));
+    }
+
+    pub fn clear(&mut self) {
+        self.active.clear();
+    }
+
+    fn take(&mut self, channel: Channel, matches: impl Fn(&Fault) -> bool) -> Option<Fault> {
+        let index = self.active.iter()
+            .position(|(target, fault)| *target == channel && fault.is_one_shot() && matches(fault))?;
+        Some(self.active.remove(index).1)
+    }
+
+    fn find<T>(&self, channel: Channel, select: impl Fn(&Fault) -> Option<T>) -> Option<T> {
+        self.active.iter()
+            .filter(|(target, _)| *target == channel)
+            .find_map(|(_, fault)| select(fault))
+    }
+
+    /// Error fault for the next request, if one is waiting for `command`.
+    pub fn take_error(&mut self, command: &str) -> Option<(String, String)> {
+        let fault = self.take(Channel::Main, |fault| matches!(fault,
+            Fault::Error { command: expected, .. } if expected.as_deref().is_none_or(|expected| expected == command)))?;
+        match fault {
+            Fault::Error { code, description, .. } => Some((code, description)),
+            _ => None,
+        }
+    }
+
+    fn delay(&self, channel: Channel) -> Option<Duration> {
+        self.find(channel, |fault| match fault {
+            Fault::Delay { ms } => Some(Duration::from_millis(*ms)),
+            _ => None,
+        })
+    }
+
+    fn split(&self, channel: Channel) -> Option<(usize, Duration)> {
+        self.find(channel, |fault| match fault {
+            Fault::SplitFrames { chunk, pause_ms } => Some((*chunk, Duration::from_millis(*pause_ms))),
+            _ => None,
+        })
+    }
+
+    fn coalesce(&self, channel: Channel) -> Option<usize> {
+        self.find(channel, |fault| match fault {
+            Fault::CoalesceFrames { frames } => Some(*frames),
+            _ => None,
+        })
+    }
+
+    pub fn keep_alive_stopped(&self) -> bool {
+        self.find(Channel::Stream, |fault| (*fault == Fault::StopKeepAlive).then_some(())).is_some()
+    }
+}
+
+/// Writes frames of one connection, applying the faults of its channel.
+pub struct FrameWriter {
+    channel: Channel,
+    held: Vec<u8>,
+    held_frames: usize,
+    held_since: Option<Instant>,
+}
+
+impl FrameWriter {
+    pub fn new(channel: Channel) -> Self {
+        Self { channel, held: Vec::new(), held_frames: 0, held_since: None }
+    }
+
+    pub fn is_holding(&self) -> bool {
+        self.held_frames > 0
+    }
+
+    pub async fn send<W: AsyncWrite + Unpin>(&mut self, socket: &mut W, shared: &Shared, message: &Value) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (delay, split, coalesce, one_shot) = {
+            let mut faults = shared.faults.lock().unwrap();
+            let one_shot = faults.take(self.channel, |fault| matches!(fault, Fault::Malformed { .. } | Fault::DropMidFrame { .. }));
+            (faults.delay(self.channel), faults.split(self.channel), faults.coalesce(self.channel), one_shot)
+        };
+
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        let mut frame = match &one_shot {
+            Some(Fault::Malformed { text }) => text.as_deref().unwrap_or(DEFAULT_MALFORMED).as_bytes().to_vec(),
+            _ => serde_json::to_vec(message)?,
+        };
+        frame.extend_from_slice(FRAME_TERMINATOR);
+
+        if let Some(Fault::DropMidFrame { bytes }) = one_shot {
+            let bytes = bytes.unwrap_or(frame.len() / 2).min(frame.len());
+            self.flush(socket, split).await?;
+            socket.write_all(&frame[..bytes]).await?;
+            socket.flush().await?;
+            let _ = socket.shutdown().await;
+            return Err("Connection dropped by injected fault".into());
+        }
+
+        self.held.extend_from_slice(&frame);
+        self.held_frames += 1;
+        self.held_since.get_or_insert_with(Instant::now);
+        if coalesce.is_some_and(|frames| self.held_frames < frames) {
+            return Ok(());
+        }
+        self.flush(socket, split).await
+    }
+
+    async fn flush<W: AsyncWrite + Unpin>(&mut self, socket: &mut W, split: Option<(usize, Duration)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.held.is_empty() {
+            return Ok(());
+        }
+        let held = std::mem::take(&mut self.held);
+        self.held_frames = 0;
+        self.held_since = None;
+        match split {
+            Some((chunk, pause)) => {
+                for (index, piece) in held.chunks(chunk.max(1)).enumerate() {
+                    if index > 0 && !pause.is_zero() {
+                        tokio::time::sleep(pause).await;
+                    }
+                    socket.write_all(piece).await?;
+                    socket.flush().await?;
+                }
+            }
+            None => {
+                socket.write_all(&held).await?;
+                socket.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes held frames once they waited `MAX_HOLD` or `CoalesceFrames` is no longer active.
+    pub async fn flush_stale<W: AsyncWrite + Unpin>(&mut self, socket: &mut W, shared: &Shared) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (coalesce, split) = {
+            let faults = shared.faults.lock().unwrap();
+            (faults.coalesce(self.channel), faults.split(self.channel))
+        };
+        if coalesce.is_none() || self.held_since.is_some_and(|since| since.elapsed() >= MAX_HOLD) {
+            self.flush(socket, split).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the frames held back by `CoalesceFrames`.
+    pub async fn flush_held<W: AsyncWrite + Unpin>(&mut self, socket: &mut W, shared: &Shared) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let split = shared.faults.lock().unwrap().split(self.channel);
+        self.flush(socket, split).await
+    }
+}
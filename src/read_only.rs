@@ -0,0 +1,87 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ClientConfig;
+use crate::history::{Candle, HistoryDownloader};
+use crate::xapi_definitions::commands_common::Period;
+use crate::xapi_definitions::commands_main::*;
+use crate::{StreamConnection, XApiClient};
+
+/// Market data, account and history commands only. There is no way to send a
+/// `TradeTransaction` through it, and the wrapped client refuses one at runtime as well.
+pub struct ReadOnlyClient {
+    client: XApiClient,
+}
+
+impl ReadOnlyClient {
+    pub fn new(mut client: XApiClient) -> Self {
+        client.read_only = true;
+        Self { client }
+    }
+
+    /// Connects to the main port of `config` and logs in.
+    pub async fn connect(config: &ClientConfig, login_request: &Request) -> Result<Self, Box<dyn Error>> {
+        let client = config.client().read_only().connect().await?;
+        Ok(Self::new(client.login(login_request).await?))
+    }
+
+    /// Stream connection of the same login, subscriptions carry no trade capability.
+    pub async fn connect_stream(&self, stream_port: &str) -> Result<StreamConnection, Box<dyn Error>> {
+        self.client.connect_stream(stream_port).await
+    }
+
+    async fn query<T>(&mut self, request: Request) -> Result<T, Box<dyn Error>>
+    where
+        T: ValidResponse + Serialize + for<'de> Deserialize<'de>,
+    {
+        self.client.execute_command(&request).await?;
+        Ok(self.client.response_data::<T>().await?.return_data)
+    }
+
+    pub async fn get_all_symbols(&mut self) -> Result<Vec<SymbolRecord>, Box<dyn Error>> {
+        self.query(Request::GetAllSymbols(GetAllSymbols {})).await
+    }
+
+    pub async fn get_symbol(&mut self, symbol: &str) -> Result<SymbolRecord, Box<dyn Error>> {
+        self.query(Request::GetSymbol(GetSymbol { symbol: symbol.into() })).await
+    }
+
+    pub async fn get_commission_def(&mut self, symbol: &str, volume: f32) -> Result<GetCommissionDefResponse, Box<dyn Error>> {
+        self.query(Request::GetCommissionDef(GetCommissionDef { symbol: symbol.into(), volume })).await
+    }
+
+    pub async fn get_margin_trade(&mut self, symbol: &str, volume: f32) -> Result<GetMarginTradeResponse, Box<dyn Error>> {
+        self.query(Request::GetMarginTrade(GetMarginTradeRequest { symbol: symbol.into(), volume })).await
+    }
+
+    pub async fn get_current_user_data(&mut self) -> Result<GetCurrentUserDataResponse, Box<dyn Error>> {
+        self.query(Request::GetCurrentUserData(GetCurrentUserData {})).await
+    }
+
+    pub async fn get_margin_level(&mut self) -> Result<GetMarginLevelResponse, Box<dyn Error>> {
+        self.query(Request::GetMarginLevel(GetMarginLevel {})).await
+    }
+
+    pub async fn get_trades(&mut self, opened_only: bool) -> Result<Vec<TradeRecord>, Box<dyn Error>> {
+        self.query(Request::GetTrades(GetTradesRequest { opened_only })).await
+    }
+
+    /// Closed trades between `start` and `end`, `end` 0 means now.
+    pub async fn get_trades_history(&mut self, start: i64, end: i64) -> Result<Vec<TradeRecord>, Box<dyn Error>> {
+        self.query(Request::GetTradesHistory(GetTradesHistoryRequest { end, start })).await
+    }
+
+    pub async fn trade_transaction_status(&mut self, order: u32) -> Result<TradeTransactionStatusResponse, Box<dyn Error>> {
+        self.query(Request::TradeTransactionStatus(TradeTransactionStatus { order })).await
+    }
+
+    pub async fn get_chart_range(&mut self, symbol: &str, period: Period, start: i64, end: i64) -> Result<Vec<Candle>, Box<dyn Error>> {
+        self.history().get_chart_range(symbol, period, start, end).await
+    }
+
+    /// Throttled candle downloads, e.g. into a `CandleStore`.
+    pub fn history(&mut self) -> HistoryDownloader<'_> {
+        HistoryDownloader::new(&mut self.client)
+    }
+}
@@ -0,0 +1,317 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tracing::warn;
+
+use crate::metrics::NoopMetrics;
+use crate::secret::{self, Secret};
+use crate::{Connection, StreamConnection, FRAME_TERMINATOR};
+
+/// Size of the in-memory pipe behind a replayed connection.
+const REPLAY_BUFFER: usize = 64 * 1024;
+/// Frames waiting for the writer thread. When it falls this far behind, e.g. on a
+/// stalled disk, further frames are dropped rather than slowing the connection down.
+pub const RECORD_BUFFER: usize = 64 * 1024;
+
+/// Main (request/reply) or stream connection of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Main,
+    Stream,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Written by the client.
+    Sent,
+    /// Read from the server.
+    Received,
+}
+
+/// One line of a recording.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RecordedFrame {
+    /// Microseconds since the recorder was created, from a monotonic clock.
+    pub elapsed_us: u64,
+    /// Wall clock time in milliseconds, for reading only.
+    pub timestamp: i64,
+    pub connection: u64,
+    pub channel: Channel,
+    pub address: String,
+    pub direction: Direction,
+    /// Frame without terminator, with `secret::SENSITIVE_KEYS` redacted.
+    pub frame: String,
+}
+
+struct RecorderInner {
+    sender: Option<SyncSender<RecordedFrame>>,
+    writer: Option<JoinHandle<()>>,
+    started: Instant,
+    next_connection: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Drop for RecorderInner {
+    /// Waits for the writer thread to write and flush the frames still queued.
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Writes frames as they arrive and flushes whenever the queue runs empty.
+fn write_frames(frames: Receiver<RecordedFrame>, file: File) {
+    let mut file = BufWriter::new(file);
+    while let Ok(mut frame) = frames.recv() {
+        loop {
+            let result = serde_json::to_writer(&mut file, &frame)
+                .map_err(std::io::Error::from)
+                .and_then(|_| file.write_all(b"\n"));
+            if let Err(err) = result {
+                warn!(connection = frame.connection, error = %err, "failed to record frame");
+            }
+            match frames.try_recv() {
+                Ok(next) => frame = next,
+                Err(_) => break,
+            }
+        }
+        if let Err(err) = file.flush() {
+            warn!(error = %err, "failed to flush recording");
+        }
+    }
+}
+
+/// Writes the frames of every connection of a client to an NDJSON file on a
+/// background thread. Clones share the file, so main and stream connections end up
+/// in one recording, which is complete once the last clone is dropped.
+///
+/// ```no_run
+/// # async fn record(config: xtb::config::ClientConfig) -> Result<(), Box<dyn std::error::Error>> {
+/// use xtb::recording::Recorder;
+///
+/// let client = config.client().record(Recorder::create("session.ndjson")?).connect().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<RecorderInner>,
+}
+
+impl Recorder {
+    /// Creates or truncates `path`.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).write(true).truncate(true).open(path.as_ref())
+            .map_err(|err| format!("Failed to create recording {}: {}", path.as_ref().display(), err))?;
+        let (sender, frames) = mpsc::sync_channel(RECORD_BUFFER);
+        let writer = std::thread::Builder::new()
+            .name("xtb-recorder".into())
+            .spawn(move || write_frames(frames, file))?;
+        Ok(Self {
+            inner: Arc::new(RecorderInner {
+                sender: Some(sender),
+                writer: Some(writer),
+                started: Instant::now(),
+                next_connection: AtomicU64::new(1),
+                dropped: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    /// Frames dropped because `RECORD_BUFFER` frames were waiting to be written.
+    pub fn dropped_frames(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Registers a new connection and returns the handle its frames are recorded with.
+    pub fn connection(&self, channel: Channel, address: &str) -> ConnectionRecorder {
+        ConnectionRecorder {
+            recorder: self.clone(),
+            connection: self.inner.next_connection.fetch_add(1, Ordering::Relaxed),
+            channel,
+            address: address.into(),
+        }
+    }
+}
+
+/// Records the frames of one connection.
+#[derive(Clone)]
+pub struct ConnectionRecorder {
+    recorder: Recorder,
+    connection: u64,
+    channel: Channel,
+    address: String,
+}
+
+impl ConnectionRecorder {
+    pub fn id(&self) -> u64 {
+        self.connection
+    }
+
+    /// Queues `frame` redacted for the writer thread, without blocking. Write errors are
+    /// logged, never returned, so a full disk does not break the session being recorded.
+    pub fn record(&self, direction: Direction, frame: &str) {
+        let inner = &self.recorder.inner;
+        let Some(sender) = &inner.sender else {
+            return;
+        };
+        let line = RecordedFrame {
+            elapsed_us: inner.started.elapsed().as_micros() as u64,
+            timestamp: Utc::now().timestamp_millis(),
+            connection: self.connection,
+            channel: self.channel,
+            address: self.address.clone(),
+            direction,
+            frame: secret::redact(frame),
+        };
+        match sender.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                if inner.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    warn!(connection = self.connection, "recording falls behind, dropping frames");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!(connection = self.connection, "recording writer stopped");
+            }
+        }
+    }
+}
+
+/// Replay speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// Frames arrive with their recorded spacing.
+    Original,
+    /// Recorded spacing divided by the factor, e.g. `Times(10.0)` is ten times faster.
+    Times(f64),
+    /// No waiting between frames.
+    Max,
+}
+
+impl Speed {
+    fn scale(&self, elapsed: Duration) -> Option<Duration> {
+        match self {
+            Speed::Original => Some(elapsed),
+            Speed::Times(factor) if *factor > 0.0 => Some(elapsed.div_f64(*factor)),
+            Speed::Times(_) | Speed::Max => None,
+        }
+    }
+}
+
+/// Frames read back from a `Recorder` file.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path.as_ref())
+            .map_err(|err| format!("Failed to read recording {}: {}", path.as_ref().display(), err))?;
+        let mut frames = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let frame = serde_json::from_str(&line)
+                .map_err(|err| format!("Invalid recording line {}: {}", index + 1, err))?;
+            frames.push(frame);
+        }
+        Ok(Self { frames })
+    }
+
+    /// Ids of the recorded connections on `channel`, in the order they were opened.
+    pub fn connections(&self, channel: Channel) -> Vec<u64> {
+        let mut connections: Vec<u64> = Vec::new();
+        for frame in self.frames.iter().filter(|frame| frame.channel == channel) {
+            if !connections.contains(&frame.connection) {
+                connections.push(frame.connection);
+            }
+        }
+        connections
+    }
+
+    /// Stream that plays back the frames `connection` received, for `XApiClient::connect_over`
+    /// or `connect_stream_over`. What the client writes is discarded, replies come in
+    /// recorded order whatever is sent. Must be called inside a tokio runtime.
+    pub fn replay(&self, connection: u64, speed: Speed) -> DuplexStream {
+        let frames: Vec<&RecordedFrame> = self.frames.iter().filter(|frame| frame.connection == connection).collect();
+        let start = frames.first().map(|frame| frame.elapsed_us).unwrap_or_default();
+        let received: Vec<(Duration, String)> = frames.into_iter()
+            .filter(|frame| frame.direction == Direction::Received)
+            .map(|frame| (Duration::from_micros(frame.elapsed_us.saturating_sub(start)), frame.frame.clone()))
+            .collect();
+
+        let (client, server) = tokio::io::duplex(REPLAY_BUFFER);
+        let (mut reader, mut writer) = tokio::io::split(server);
+        tokio::spawn(async move {
+            let mut discard = vec![0; REPLAY_BUFFER];
+            while matches!(reader.read(&mut discard).await, Ok(n) if n > 0) {}
+        });
+        tokio::spawn(async move {
+            let started = tokio::time::Instant::now();
+            for (elapsed, frame) in received {
+                if let Some(delay) = speed.scale(elapsed) {
+                    tokio::time::sleep_until(started + delay).await;
+                }
+                let mut bytes = frame.into_bytes();
+                bytes.extend_from_slice(FRAME_TERMINATOR);
+                if writer.write_all(&bytes).await.is_err() {
+                    return;
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+        client
+    }
+
+    /// Stream connection replaying `connection`, for stream consumers without a login.
+    pub fn replay_stream(&self, connection: u64, speed: Speed) -> StreamConnection {
+        let address = self.frames.iter()
+            .find(|frame| frame.connection == connection)
+            .map(|frame| frame.address.clone())
+            .unwrap_or_else(|| "replay".into());
+        StreamConnection {
+            connection: Connection::over(Box::new(self.replay(connection, speed)), address, Arc::new(NoopMetrics), false),
+            stream_session_id: Secret::new("replay"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_written_redacted_when_the_recorder_is_dropped() {
+        let path = std::env::temp_dir().join(format!("xtb-recording-{}.ndjson", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        let main = recorder.connection(Channel::Main, "localhost:5124");
+        let stream = recorder.connection(Channel::Stream, "localhost:5125");
+        main.record(Direction::Sent, r#"{"command":"login","arguments":{"password":"hunter2"}}"#);
+        stream.record(Direction::Received, r#"{"command":"keepAlive"}"#);
+        drop((recorder, main, stream));
+
+        let recording = Recording::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recording.frames.len(), 2);
+        assert_eq!(recording.frames[0].frame, r#"{"command":"login","arguments":{"password":"***"}}"#);
+        assert_eq!(recording.connections(Channel::Stream), vec![2]);
+    }
+}
@@ -0,0 +1,668 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use tracing::warn;
+
+use crate::broker::Broker;
+use crate::sim::DEFAULT_CONTRACT_SIZE;
+use crate::xapi_definitions::commands_common::{Cmd, RequestStatus, State, Type};
+use crate::xapi_definitions::commands_main::*;
+use crate::xapi_definitions::commands_stream::*;
+
+#[derive(Debug, Clone)]
+pub struct RiskLimits {
+    /// Max net volume in lots per symbol, buys minus sells, pending orders included.
+    /// `default_max_position_volume` applies to unlisted symbols.
+    pub max_position_volume: HashMap<String, f64>,
+    pub default_max_position_volume: Option<f64>,
+    /// Max notional of all open positions and pending orders in account currency.
+    pub max_total_exposure: Option<f64>,
+    /// Max open positions and pending orders, together.
+    pub max_open_positions: Option<usize>,
+    /// Max realized loss per UTC day, as a positive amount.
+    pub max_daily_loss: Option<f64>,
+    /// Max number of orders within the given window.
+    pub max_order_rate: Option<(usize, Duration)>,
+    /// Contract size per symbol, see `symbol`. `default_contract_size` applies to unlisted symbols.
+    pub contract_sizes: HashMap<String, f64>,
+    pub default_contract_size: f64,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        Self {
+            max_position_volume: HashMap::new(),
+            default_max_position_volume: None,
+            max_total_exposure: None,
+            max_open_positions: None,
+            max_daily_loss: None,
+            max_order_rate: None,
+            contract_sizes: HashMap::new(),
+            default_contract_size: DEFAULT_CONTRACT_SIZE,
+        }
+    }
+}
+
+impl RiskLimits {
+    /// Takes the contract size of a symbol from its `getSymbol` record.
+    pub fn symbol(mut self, symbol: &SymbolRecord) -> Self {
+        self.contract_sizes.insert(symbol.symbol.clone(), symbol.contract_size as f64);
+        self
+    }
+
+    pub fn contract_size(&self, symbol: &str) -> f64 {
+        self.contract_sizes.get(symbol).copied().unwrap_or(self.default_contract_size)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskViolation {
+    KillSwitch,
+    PositionVolume { symbol: String, volume: f64, limit: f64 },
+    TotalExposure { exposure: f64, limit: f64 },
+    OpenPositions { limit: usize },
+    DailyLoss { realized: f64, limit: f64 },
+    OrderRate { limit: usize, window: Duration },
+    NoPrice { symbol: String },
+}
+
+impl fmt::Display for RiskViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskViolation::KillSwitch =>
+                write!(f, "Kill switch engaged, new orders are blocked"),
+            RiskViolation::PositionVolume { symbol, volume, limit } =>
+                write!(f, "Position volume {} on {} would exceed limit {}", volume, symbol, limit),
+            RiskViolation::TotalExposure { exposure, limit } =>
+                write!(f, "Total exposure {:.2} would exceed limit {:.2}", exposure, limit),
+            RiskViolation::OpenPositions { limit } =>
+                write!(f, "Open positions limit {} reached", limit),
+            RiskViolation::DailyLoss { realized, limit } =>
+                write!(f, "Daily realized loss {:.2} reached limit {:.2}", -realized, limit),
+            RiskViolation::OrderRate { limit, window } =>
+                write!(f, "Order rate limit of {} per {:?} exceeded", limit, window),
+            RiskViolation::NoPrice { symbol } =>
+                write!(f, "No price known for {}, cannot compute exposure", symbol),
+        }
+    }
+}
+
+impl Error for RiskViolation {}
+
+/// Shared flag blocking new orders, clone it to engage from another task.
+#[derive(Debug, Clone, Default)]
+pub struct KillSwitch(Arc<AtomicBool>);
+
+impl KillSwitch {
+    pub fn engage(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn release(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TrackedPosition {
+    symbol: String,
+    cmd: Cmd,
+    volume: f64,
+    price: f64,
+    /// Pending order rather than an open position.
+    pending: bool,
+}
+
+impl TrackedPosition {
+    fn order(trade_trans_info: &TradeTransInfo) -> Self {
+        Self {
+            symbol: trade_trans_info.symbol.clone(),
+            cmd: trade_trans_info.cmd,
+            volume: trade_trans_info.volume as f64,
+            price: trade_trans_info.price as f64,
+            pending: matches!(trade_trans_info.cmd, Cmd::BuyLimit | Cmd::SellLimit | Cmd::BuyStop | Cmd::SellStop),
+        }
+    }
+
+    /// Volume signed by direction, positive for buys.
+    fn net_volume(&self) -> f64 {
+        match self.cmd {
+            Cmd::Buy | Cmd::BuyLimit | Cmd::BuyStop => self.volume,
+            Cmd::Sell | Cmd::SellLimit | Cmd::SellStop => -self.volume,
+            Cmd::Balance | Cmd::Credit | Cmd::Unknown(_) => 0.0,
+        }
+    }
+}
+
+/// Pre-trade checks in front of a `Broker`. Positions, pending orders, prices and
+/// realized profit are tracked from the events passing through `next_event`; orders
+/// sent through it count against the limits from the moment the broker accepts them.
+pub struct RiskManager<B: Broker> {
+    broker: B,
+    limits: RiskLimits,
+    kill_switch: KillSwitch,
+    /// Open positions and pending orders seen on the stream.
+    positions: HashMap<u32, TrackedPosition>,
+    /// Orders accepted by the broker and not yet seen on the stream.
+    submitted: HashMap<u32, TrackedPosition>,
+    prices: HashMap<String, f64>,
+    orders: VecDeque<Instant>,
+    day: NaiveDate,
+    realized: f64,
+}
+
+impl<B: Broker> RiskManager<B> {
+    pub fn new(broker: B, limits: RiskLimits) -> Self {
+        Self {
+            broker,
+            limits,
+            kill_switch: KillSwitch::default(),
+            positions: HashMap::new(),
+            submitted: HashMap::new(),
+            prices: HashMap::new(),
+            orders: VecDeque::new(),
+            day: Utc::now().date_naive(),
+            realized: 0.0,
+        }
+    }
+
+    pub fn kill_switch(&self) -> KillSwitch {
+        self.kill_switch.clone()
+    }
+
+    pub fn limits(&self) -> &RiskLimits {
+        &self.limits
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.broker
+    }
+
+    pub fn realized_today(&self) -> f64 {
+        self.realized
+    }
+
+    fn tracked(&self) -> impl Iterator<Item = &TrackedPosition> {
+        self.positions.values().chain(self.submitted.values())
+    }
+
+    fn roll_day(&mut self, today: NaiveDate) {
+        if today != self.day {
+            self.day = today;
+            self.realized = 0.0;
+        }
+    }
+
+    /// Validates a trade request without sending it.
+    pub fn check(&mut self, trade_trans_info: &TradeTransInfo) -> Result<(), RiskViolation> {
+        let now = Instant::now();
+        if let Some((limit, window)) = self.limits.max_order_rate {
+            while self.orders.front().is_some_and(|sent| now.duration_since(*sent) > window) {
+                self.orders.pop_front();
+            }
+            if self.orders.len() >= limit {
+                return Err(RiskViolation::OrderRate { limit, window });
+            }
+        }
+
+        let opening = matches!(trade_trans_info.r#type, Type::Open | Type::Pending);
+        if opening {
+            self.check_open(trade_trans_info)?;
+        }
+
+        self.orders.push_back(now);
+        Ok(())
+    }
+
+    fn check_open(&mut self, trade_trans_info: &TradeTransInfo) -> Result<(), RiskViolation> {
+        if self.kill_switch.is_engaged() {
+            return Err(RiskViolation::KillSwitch);
+        }
+
+        self.roll_day(Utc::now().date_naive());
+        if let Some(limit) = self.limits.max_daily_loss {
+            if self.realized <= -limit {
+                return Err(RiskViolation::DailyLoss { realized: self.realized, limit });
+            }
+        }
+
+        if let Some(limit) = self.limits.max_open_positions {
+            if self.tracked().count() >= limit {
+                return Err(RiskViolation::OpenPositions { limit });
+            }
+        }
+
+        let order = TrackedPosition::order(trade_trans_info);
+        let symbol = &trade_trans_info.symbol;
+        let volume = order.volume;
+        let limit = self.limits.max_position_volume.get(symbol).copied()
+            .or(self.limits.default_max_position_volume);
+        if let Some(limit) = limit {
+            let net: f64 = self.tracked()
+                .filter(|position| &position.symbol == symbol)
+                .map(TrackedPosition::net_volume)
+                .sum();
+            let after = net + order.net_volume();
+            // Orders bringing the net volume back towards the limit are always allowed.
+            if after.abs() > limit && after.abs() > net.abs() {
+                return Err(RiskViolation::PositionVolume { symbol: symbol.clone(), volume: after.abs(), limit });
+            }
+        }
+
+        if let Some(limit) = self.limits.max_total_exposure {
+            let price = match self.prices.get(symbol) {
+                Some(price) => *price,
+                None if trade_trans_info.price > 0.0 => trade_trans_info.price as f64,
+                None => return Err(RiskViolation::NoPrice { symbol: symbol.clone() }),
+            };
+            let exposure = self.exposure() + volume * self.limits.contract_size(symbol) * price;
+            if exposure > limit {
+                return Err(RiskViolation::TotalExposure { exposure, limit });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Notional of all open positions and pending orders at the latest known prices.
+    pub fn exposure(&self) -> f64 {
+        self.tracked()
+            .map(|position| {
+                let price = self.prices.get(&position.symbol).copied().unwrap_or(position.price);
+                position.volume * self.limits.contract_size(&position.symbol) * price
+            })
+            .sum()
+    }
+
+    fn observe(&mut self, event: &ResponseStream) {
+        match event {
+            ResponseStream::TickPrices(tick) => {
+                self.prices.insert(tick.data.symbol.clone(), tick.data.bid as f64);
+            }
+            ResponseStream::Trade(trade) => {
+                let trade = &trade.data;
+                for order in [trade.order, trade.order2, trade.position] {
+                    self.submitted.remove(&order);
+                }
+                if trade.closed {
+                    self.roll_day(Utc::now().date_naive());
+                    let close_day = trade.close_time
+                        .and_then(DateTime::from_timestamp_millis)
+                        .map(|close_time| close_time.date_naive());
+                    if close_day.is_none_or(|close_day| close_day == self.day) {
+                        self.realized += trade.profit.unwrap_or_default() as f64;
+                    }
+                    self.positions.remove(&trade.position);
+                } else if trade.state == State::Deleted {
+                    self.positions.remove(&trade.position);
+                } else if matches!(trade.r#type, Type::Open | Type::Pending) {
+                    self.positions.insert(trade.position, TrackedPosition {
+                        symbol: trade.symbol.clone(),
+                        cmd: trade.cmd,
+                        volume: trade.volume as f64,
+                        price: trade.open_price as f64,
+                        pending: trade.r#type == Type::Pending,
+                    });
+                }
+            }
+            ResponseStream::TradeStatus(status) => {
+                if matches!(status.data.request_status, RequestStatus::Rejected | RequestStatus::Error) {
+                    self.submitted.remove(&status.data.order);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Engages the kill switch and, if requested, closes every tracked position and
+    /// deletes every pending order, including orders not yet seen on the stream. Every
+    /// close is attempted, the ones that failed are reported together afterwards.
+    pub async fn kill(&mut self, close_positions: bool) -> Result<(), Box<dyn Error>> {
+        self.kill_switch.engage();
+        if !close_positions {
+            return Ok(());
+        }
+
+        let positions: BTreeMap<u32, TrackedPosition> = self.submitted.iter().chain(&self.positions)
+            .map(|(order, position)| (*order, position.clone()))
+            .collect();
+        let mut errors = Vec::new();
+        for (order, position) in positions {
+            let close = TradeTransInfo {
+                cmd: position.cmd,
+                custom_comment: Some("kill switch".into()),
+                expiration: 0,
+                offset: 0,
+                order,
+                price: 0.0,
+                sl: 0.0,
+                symbol: position.symbol,
+                tp: 0.0,
+                r#type: if position.pending { Type::Delete } else { Type::Close },
+                volume: position.volume as f32,
+            };
+            if let Err(err) = self.broker.trade_transaction(close).await {
+                warn!(order, error = %err, "kill switch failed to close order");
+                errors.push(format!("{}: {}", order, err));
+            }
+        }
+        if !errors.is_empty() {
+            Err(format!("Kill switch failed to close orders {}", errors.join(", ")))?;
+        }
+        Ok(())
+    }
+}
+
+impl<B: Broker> Broker for RiskManager<B> {
+    async fn trade_transaction(&mut self, trade_trans_info: TradeTransInfo) -> Result<u32, Box<dyn Error>> {
+        self.check(&trade_trans_info)?;
+        let opening = matches!(trade_trans_info.r#type, Type::Open | Type::Pending);
+        let tracked = opening.then(|| TrackedPosition::order(&trade_trans_info));
+        let order = self.broker.trade_transaction(trade_trans_info).await?;
+        if let Some(tracked) = tracked {
+            self.submitted.insert(order, tracked);
+        }
+        Ok(order)
+    }
+
+    async fn trade_transaction_status(&mut self, order: u32) -> Result<TradeTransactionStatusResponse, Box<dyn Error>> {
+        self.broker.trade_transaction_status(order).await
+    }
+
+    async fn next_event(&mut self) -> Result<ResponseStream, Box<dyn Error>> {
+        let event = self.broker.next_event().await?;
+        self.observe(&event);
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xapi_definitions::commands_stream::GetResponse;
+
+    /// Accepts every order, numbering them from 1, except closes of `refused`.
+    #[derive(Default)]
+    struct Accepting {
+        next_order: u32,
+        refused: Vec<u32>,
+        sent: Vec<TradeTransInfo>,
+    }
+
+    impl Broker for Accepting {
+        async fn trade_transaction(&mut self, trade_trans_info: TradeTransInfo) -> Result<u32, Box<dyn Error>> {
+            let closing = matches!(trade_trans_info.r#type, Type::Close | Type::Delete);
+            let refused = closing && self.refused.contains(&trade_trans_info.order);
+            self.sent.push(trade_trans_info);
+            if refused {
+                Err("refused")?;
+            }
+            self.next_order += 1;
+            Ok(self.next_order)
+        }
+
+        async fn trade_transaction_status(&mut self, _order: u32) -> Result<TradeTransactionStatusResponse, Box<dyn Error>> {
+            Err("not used")?
+        }
+
+        async fn next_event(&mut self) -> Result<ResponseStream, Box<dyn Error>> {
+            Err("not used")?
+        }
+    }
+
+    fn manager(limits: RiskLimits) -> RiskManager<Accepting> {
+        RiskManager::new(Accepting::default(), limits)
+    }
+
+    fn order(cmd: Cmd, volume: f32, price: f32) -> TradeTransInfo {
+        TradeTransInfo {
+            cmd,
+            custom_comment: None,
+            expiration: 0,
+            offset: 0,
+            order: 0,
+            price,
+            sl: 0.0,
+            symbol: "EURUSD".into(),
+            tp: 0.0,
+            r#type: Type::Open,
+            volume,
+        }
+    }
+
+    fn trade(position: u32, cmd: Cmd, volume: f32, r#type: Type, state: State) -> GetTradesReponse {
+        GetTradesReponse {
+            close_price: 0.0,
+            close_time: None,
+            closed: false,
+            cmd,
+            comment: String::new(),
+            commission: 0.0,
+            custom_comment: None,
+            digits: 5,
+            expiration: None,
+            margin_rate: 0.0,
+            offset: 0,
+            open_price: 1.0,
+            open_time: 0,
+            order: position,
+            order2: position,
+            position,
+            profit: None,
+            sl: 0.0,
+            state,
+            storage: 0.0,
+            symbol: "EURUSD".into(),
+            tp: 0.0,
+            r#type,
+            volume,
+            extra: Default::default(),
+        }
+    }
+
+    fn event(trade: GetTradesReponse) -> ResponseStream {
+        ResponseStream::Trade(GetResponse { data: trade, extra: Default::default() })
+    }
+
+    fn price(bid: f32) -> ResponseStream {
+        ResponseStream::TickPrices(GetResponse {
+            data: GetTickPricesResponse {
+                ask: bid,
+                ask_volume: 0,
+                bid,
+                bid_volume: 0,
+                high: bid,
+                level: 0,
+                low: bid,
+                quote_id: 0,
+                spread_raw: 0.0,
+                spread_table: 0.0,
+                symbol: "EURUSD".into(),
+                timestamp: 0,
+                extra: Default::default(),
+            },
+            extra: Default::default(),
+        })
+    }
+
+    #[test]
+    fn position_volume_is_net_by_direction() {
+        let mut risk = manager(RiskLimits { default_max_position_volume: Some(1.0), ..Default::default() });
+        risk.observe(&event(trade(1, Cmd::Buy, 0.8, Type::Open, State::Modified)));
+
+        assert!(matches!(risk.check(&order(Cmd::Buy, 0.3, 0.0)), Err(RiskViolation::PositionVolume { .. })));
+        risk.check(&order(Cmd::Sell, 0.3, 0.0)).unwrap();
+
+        // A pending buy limit counts in the direction it opens.
+        risk.observe(&event(trade(2, Cmd::BuyLimit, 0.2, Type::Pending, State::Modified)));
+        assert!(matches!(risk.check(&order(Cmd::BuyStop, 0.1, 1.2)), Err(RiskViolation::PositionVolume { .. })));
+    }
+
+    #[test]
+    fn orders_reducing_net_volume_pass_above_the_limit() {
+        let mut risk = manager(RiskLimits { default_max_position_volume: Some(0.5), ..Default::default() });
+        risk.observe(&event(trade(1, Cmd::Buy, 0.8, Type::Open, State::Modified)));
+
+        risk.check(&order(Cmd::Sell, 0.1, 0.0)).unwrap();
+        assert!(matches!(risk.check(&order(Cmd::Sell, 1.7, 0.0)), Err(RiskViolation::PositionVolume { .. })));
+    }
+
+    #[test]
+    fn pending_orders_count_as_open_positions() {
+        let mut risk = manager(RiskLimits { max_open_positions: Some(2), ..Default::default() });
+        risk.observe(&event(trade(1, Cmd::Buy, 0.1, Type::Open, State::Modified)));
+        risk.observe(&event(trade(2, Cmd::SellLimit, 0.1, Type::Pending, State::Modified)));
+
+        assert_eq!(risk.check(&order(Cmd::Buy, 0.1, 0.0)), Err(RiskViolation::OpenPositions { limit: 2 }));
+
+        risk.observe(&event(trade(2, Cmd::SellLimit, 0.1, Type::Pending, State::Deleted)));
+        risk.check(&order(Cmd::Buy, 0.1, 0.0)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn submitted_orders_count_until_seen_or_rejected() {
+        let mut risk = manager(RiskLimits { max_open_positions: Some(1), ..Default::default() });
+        let first = risk.trade_transaction(order(Cmd::BuyLimit, 0.1, 0.9)).await.unwrap();
+
+        let err = risk.trade_transaction(order(Cmd::BuyLimit, 0.1, 0.9)).await.unwrap_err();
+        assert_eq!(err.to_string(), RiskViolation::OpenPositions { limit: 1 }.to_string());
+
+        risk.observe(&ResponseStream::TradeStatus(GetResponse {
+            data: GetTradeStatusResponse {
+                custom_comment: None,
+                message: Some("rejected".into()),
+                order: first,
+                price: 0.0,
+                request_status: RequestStatus::Rejected,
+                extra: Default::default(),
+            },
+            extra: Default::default(),
+        }));
+        let second = risk.trade_transaction(order(Cmd::BuyLimit, 0.1, 0.9)).await.unwrap();
+
+        // Seen on the stream, it moves from submitted to tracked and still counts once.
+        risk.observe(&event(trade(second, Cmd::BuyLimit, 0.1, Type::Pending, State::Modified)));
+        assert_eq!(risk.tracked().count(), 1);
+    }
+
+    #[test]
+    fn total_exposure_includes_pending_orders() {
+        let mut risk = manager(RiskLimits { max_total_exposure: Some(150_000.0), ..Default::default() });
+        risk.observe(&price(1.0));
+        risk.observe(&event(trade(1, Cmd::BuyLimit, 1.0, Type::Pending, State::Modified)));
+
+        assert_eq!(risk.exposure(), 100_000.0);
+        assert!(matches!(risk.check(&order(Cmd::Sell, 1.0, 0.0)), Err(RiskViolation::TotalExposure { .. })));
+        risk.check(&order(Cmd::Sell, 0.4, 0.0)).unwrap();
+    }
+
+    #[test]
+    fn exposure_needs_a_price() {
+        let mut risk = manager(RiskLimits { max_total_exposure: Some(150_000.0), ..Default::default() });
+        assert_eq!(risk.check(&order(Cmd::Buy, 0.1, 0.0)), Err(RiskViolation::NoPrice { symbol: "EURUSD".into() }));
+        risk.check(&order(Cmd::BuyLimit, 0.1, 0.9)).unwrap();
+    }
+
+    #[test]
+    fn daily_loss_blocks_opening_but_not_closing() {
+        let mut risk = manager(RiskLimits { max_daily_loss: Some(50.0), ..Default::default() });
+        risk.observe(&event(GetTradesReponse {
+            closed: true,
+            close_time: Some(Utc::now().timestamp_millis()),
+            profit: Some(-60.0),
+            ..trade(1, Cmd::Buy, 0.1, Type::Close, State::Modified)
+        }));
+
+        assert_eq!(risk.check(&order(Cmd::Buy, 0.1, 0.0)), Err(RiskViolation::DailyLoss { realized: -60.0, limit: 50.0 }));
+        risk.check(&TradeTransInfo { r#type: Type::Close, order: 2, ..order(Cmd::Buy, 0.1, 0.0) }).unwrap();
+    }
+
+    #[test]
+    fn order_rate_counts_every_order() {
+        let window = Duration::from_secs(60);
+        let mut risk = manager(RiskLimits { max_order_rate: Some((2, window)), ..Default::default() });
+        risk.check(&order(Cmd::Buy, 0.1, 0.0)).unwrap();
+        risk.check(&TradeTransInfo { r#type: Type::Close, order: 1, ..order(Cmd::Buy, 0.1, 0.0) }).unwrap();
+
+        assert_eq!(risk.check(&order(Cmd::Buy, 0.1, 0.0)), Err(RiskViolation::OrderRate { limit: 2, window }));
+    }
+
+    #[test]
+    fn kill_switch_blocks_opening_until_released() {
+        let mut risk = manager(RiskLimits::default());
+        let kill_switch = risk.kill_switch();
+        kill_switch.engage();
+
+        assert_eq!(risk.check(&order(Cmd::Buy, 0.1, 0.0)), Err(RiskViolation::KillSwitch));
+        risk.check(&TradeTransInfo { r#type: Type::Close, order: 1, ..order(Cmd::Buy, 0.1, 0.0) }).unwrap();
+
+        kill_switch.release();
+        risk.check(&order(Cmd::Buy, 0.1, 0.0)).unwrap();
+    }
+
+    #[test]
+    fn exposure_uses_the_contract_size_of_each_symbol() {
+        let index = SymbolRecord {
+            ask: 5001.0,
+            bid: 5000.0,
+            category_name: "IND".into(),
+            contract_size: 50,
+            currency: "USD".into(),
+            currency_profit: "USD".into(),
+            description: "US 500".into(),
+            digits: 1,
+            high: 5001.0,
+            leverage: 5.0,
+            lot_max: 100.0,
+            lot_min: 0.01,
+            lot_step: 0.01,
+            low: 5000.0,
+            precision: 1,
+            spread_raw: 1.0,
+            spread_table: 1.0,
+            symbol: "US500".into(),
+            time: 0,
+            trailing_enabled: true,
+            extra: Default::default(),
+        };
+        let limits = RiskLimits { max_total_exposure: Some(400_000.0), ..Default::default() }.symbol(&index);
+        let mut risk = manager(limits);
+        risk.observe(&price(1.0));
+        risk.observe(&event(GetTradesReponse {
+            symbol: "US500".into(),
+            open_price: 5000.0,
+            ..trade(1, Cmd::Buy, 1.0, Type::Open, State::Modified)
+        }));
+
+        assert_eq!(risk.exposure(), 250_000.0);
+        // EURUSD is unlisted and keeps the default contract size.
+        risk.check(&order(Cmd::Buy, 1.5, 0.0)).unwrap();
+        assert!(matches!(risk.check(&order(Cmd::Buy, 1.6, 0.0)), Err(RiskViolation::TotalExposure { .. })));
+    }
+
+    #[tokio::test]
+    async fn kill_closes_submitted_orders_and_attempts_every_close() {
+        let mut risk = manager(RiskLimits::default());
+        risk.observe(&event(trade(10, Cmd::Buy, 0.1, Type::Open, State::Modified)));
+        risk.observe(&event(trade(11, Cmd::SellLimit, 0.2, Type::Pending, State::Modified)));
+        let submitted = risk.trade_transaction(order(Cmd::BuyLimit, 0.3, 0.9)).await.unwrap();
+        risk.broker.refused.push(10);
+        risk.broker.sent.clear();
+
+        let err = risk.kill(true).await.unwrap_err();
+        assert!(err.to_string().contains("10: refused"), "{}", err);
+        assert!(risk.kill_switch().is_engaged());
+
+        let closes: Vec<(u32, Type)> = risk.broker.sent.iter().map(|close| (close.order, close.r#type)).collect();
+        assert_eq!(closes, [(submitted, Type::Delete), (10, Type::Close), (11, Type::Delete)]);
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+/// Keys whose values are replaced by `redact`.
+pub const SENSITIVE_KEYS: &[&str] = &["password", "streamSessionId"];
+
+const REDACTED: &str = "***";
+
+/// String that never shows up in `Debug`/`Display` output and is zeroed on drop.
+/// Serializes as the plain value so it can be sent on the wire.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new<S: Into<String>>(value: S) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<&String> for Secret {
+    fn from(value: &String) -> Self {
+        Self(value.clone())
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+/// Replaces the string values of `SENSITIVE_KEYS` in a raw JSON frame, even when
+/// the frame is truncated or otherwise not valid JSON.
+pub fn redact(frame: &str) -> String {
+    let mut redacted = frame.to_string();
+    for key in SENSITIVE_KEYS {
+        let pattern = format!("\"{}\"", key);
+        let mut from = 0;
+        while let Some(found) = redacted[from..].find(&pattern) {
+            let mut pos = from + found + pattern.len();
+            let bytes = redacted.as_bytes();
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= bytes.len() || bytes[pos] != b':' {
+                from = pos;
+                continue;
+            }
+            pos += 1;
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos >= bytes.len() || bytes[pos] != b'"' {
+                from = pos;
+                continue;
+            }
+
+            let start = pos + 1;
+            let mut end = start;
+            let mut escaped = false;
+            while end < bytes.len() && (escaped || bytes[end] != b'"') {
+                escaped = !escaped && bytes[end] == b'\\';
+                end += 1;
+            }
+            redacted.replace_range(start..end, REDACTED);
+            from = start + REDACTED.len();
+        }
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_sensitive_values_only() {
+        let frame = r#"{"command":"login","arguments":{"userId":"12345","password":"hunter2"}}"#;
+        assert_eq!(redact(frame), r#"{"command":"login","arguments":{"userId":"12345","password":"***"}}"#);
+
+        let frame = r#"{"command":"getTickPrices","streamSessionId":"8469308861804289383","symbol":"EURUSD"}"#;
+        assert_eq!(redact(frame), r#"{"command":"getTickPrices","streamSessionId":"***","symbol":"EURUSD"}"#);
+    }
+
+    #[test]
+    fn redacts_every_occurrence() {
+        let frame = r#"[{"password":"a"},{"password":"b","streamSessionId":"c"}]"#;
+        assert_eq!(redact(frame), r#"[{"password":"***"},{"password":"***","streamSessionId":"***"}]"#);
+    }
+
+    #[test]
+    fn allows_whitespace_around_colon() {
+        let frame = "{\"password\" :\n\t \"hunter2\" , \"userId\": \"1\"}";
+        assert_eq!(redact(frame), "{\"password\" :\n\t \"***\" , \"userId\": \"1\"}");
+    }
+
+    #[test]
+    fn skips_escaped_quotes_inside_the_value() {
+        let frame = r#"{"password":"a\"b\\","userId":"1"}"#;
+        assert_eq!(redact(frame), r#"{"password":"***","userId":"1"}"#);
+
+        let frame = r#"{"password":"\\\"","userId":"1"}"#;
+        assert_eq!(redact(frame), r#"{"password":"***","userId":"1"}"#);
+    }
+
+    #[test]
+    fn redacts_truncated_frames() {
+        assert_eq!(redact(r#"{"userId":"1","password":"hunt"#), r#"{"userId":"1","password":"***"#);
+        assert_eq!(redact(r#"{"password":"a\"#), r#"{"password":"***"#);
+        assert_eq!(redact(r#"{"password":"#), r#"{"password":"#);
+        assert_eq!(redact(r#"{"password" "#), r#"{"password" "#);
+        assert_eq!(redact(r#"{"passw"#), r#"{"passw"#);
+    }
+
+    #[test]
+    fn keeps_keys_used_as_values_and_non_string_values() {
+        let frame = r#"{"field":"password","password":null,"newPassword":"x"}"#;
+        assert_eq!(redact(frame), frame);
+    }
+
+    #[test]
+    fn keeps_multibyte_text_intact() {
+        let frame = r#"{"comment":"zażółć","password":"gęślą jaźń","userId":"ü"}"#;
+        assert_eq!(redact(frame), r#"{"comment":"zażółć","password":"***","userId":"ü"}"#);
+    }
+
+    #[test]
+    fn secret_is_hidden_from_formatting_but_serialized() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{} {:?}", secret, secret), "*** Secret(***)");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), r#""hunter2""#);
+    }
+}
@@ -0,0 +1,186 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::ClientConfig;
+use crate::metrics::{Metrics, NoopMetrics};
+use crate::recording::Recorder;
+use crate::xapi_definitions::commands_main::{LoginRequest, Request};
+use crate::xapi_definitions::commands_stream::*;
+use crate::{StreamConnection, XApiClient};
+
+/// Minimum gap between subscriptions, the server drops bursts of stream commands.
+pub const SUBSCRIBE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Main and stream connections of one login. The stream session id returned by
+/// the login stays inside the stream connection and is added to every subscription.
+pub struct XApiSession {
+    client: XApiClient,
+    stream: StreamConnection,
+    last_subscribe: Option<Instant>,
+    config: ClientConfig,
+    login: LoginRequest,
+    metrics: Arc<dyn Metrics>,
+    recorder: Option<Recorder>,
+    log_payloads: bool,
+    /// Subscriptions sent so far, repeated by `reconnect`.
+    subscriptions: Vec<RequestStream>,
+}
+
+impl XApiSession {
+    /// Connects to both ports of `config` and logs in.
+    pub async fn connect(config: &ClientConfig, login_request: &Request) -> Result<Self, Box<dyn Error>> {
+        Self::connect_with_metrics(config, login_request, Arc::new(NoopMetrics)).await
+    }
+
+    pub async fn connect_with_metrics(
+        config: &ClientConfig,
+        login_request: &Request,
+        metrics: Arc<dyn Metrics>,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::connect_with(config, login_request, metrics, None).await
+    }
+
+    /// Like `connect_with_metrics`, recording both connections to `recorder` when given.
+    pub async fn connect_with(
+        config: &ClientConfig,
+        login_request: &Request,
+        metrics: Arc<dyn Metrics>,
+        recorder: Option<Recorder>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let Request::Login(login) = login_request else {
+            Err("Expected a login request")?
+        };
+        let (client, stream) = Self::open(config, login, metrics.clone(), recorder.clone()).await?;
+        Ok(Self {
+            client,
+            stream,
+            last_subscribe: None,
+            config: config.clone(),
+            login: login.clone(),
+            metrics,
+            recorder,
+            log_payloads: false,
+            subscriptions: Vec::new(),
+        })
+    }
+
+    async fn open(
+        config: &ClientConfig,
+        login: &LoginRequest,
+        metrics: Arc<dyn Metrics>,
+        recorder: Option<Recorder>,
+    ) -> Result<(XApiClient, StreamConnection), Box<dyn Error>> {
+        let client = config.client().with_metrics(metrics);
+        let client = match recorder {
+            Some(recorder) => client.record(recorder),
+            None => client,
+        };
+        let client = client.connect().await?;
+        let client = client.login(&Request::Login(login.clone())).await?;
+        let stream = client.connect_stream(&config.stream_port.to_string()).await?;
+        Ok((client, stream))
+    }
+
+    /// Connects and logs in again, then repeats every subscription, e.g. after `next_event`
+    /// failed with `ConnectionClosed`. Reported through `Metrics::reconnect`.
+    pub async fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        let (client, stream) = Self::open(&self.config, &self.login, self.metrics.clone(), self.recorder.clone()).await?;
+        self.client = client;
+        self.stream = stream;
+        self.last_subscribe = None;
+        self.log_payloads(self.log_payloads);
+        self.metrics.reconnect(&format!("{}:{}", self.config.host, self.config.port));
+        for request in self.subscriptions.clone() {
+            self.send_subscription(&request).await?;
+        }
+        Ok(())
+    }
+
+    pub fn log_payloads(&mut self, log_payloads: bool) {
+        self.log_payloads = log_payloads;
+        self.client.log_payloads(log_payloads);
+        self.stream.log_payloads(log_payloads);
+    }
+
+    /// Whether trade transactions are refused, from `ClientConfig::read_only`.
+    pub fn is_read_only(&self) -> bool {
+        self.client.is_read_only()
+    }
+
+    /// Main connection, for requests and trade transactions.
+    pub fn client(&mut self) -> &mut XApiClient {
+        &mut self.client
+    }
+
+    /// Stream connection, already bound to the session.
+    pub fn stream(&mut self) -> &mut StreamConnection {
+        &mut self.stream
+    }
+
+    /// Main and stream connections, e.g. to read the stream on another task.
+    pub fn into_parts(self) -> (XApiClient, StreamConnection) {
+        (self.client, self.stream)
+    }
+
+    pub async fn subscribe(&mut self, request: &RequestStream) -> Result<(), Box<dyn Error>> {
+        self.send_subscription(request).await?;
+        self.subscriptions.push(request.clone());
+        Ok(())
+    }
+
+    async fn send_subscription(&mut self, request: &RequestStream) -> Result<(), Box<dyn Error>> {
+        if let Some(last_subscribe) = self.last_subscribe {
+            let elapsed = last_subscribe.elapsed();
+            if elapsed < SUBSCRIBE_INTERVAL {
+                tokio::time::sleep(SUBSCRIBE_INTERVAL - elapsed).await;
+            }
+        }
+        self.stream.subscribe(request).await?;
+        self.last_subscribe = Some(Instant::now());
+        Ok(())
+    }
+
+    pub async fn subscribe_tick_prices(
+        &mut self,
+        symbol: &str,
+        min_arrival_time: Option<i32>,
+        max_level: Option<i32>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.subscribe(&RequestStream::GetTickPrices(
+            GetTickPrices {
+                symbol: symbol.into(),
+                min_arrival_time,
+                max_level,
+            }
+        )).await
+    }
+
+    pub async fn subscribe_candles(&mut self, symbol: &str) -> Result<(), Box<dyn Error>> {
+        self.subscribe(&RequestStream::GetCandles(GetCandles { symbol: symbol.into() })).await
+    }
+
+    pub async fn subscribe_balance(&mut self) -> Result<(), Box<dyn Error>> {
+        self.subscribe(&RequestStream::GetBalance(GetBalance {})).await
+    }
+
+    pub async fn subscribe_keep_alive(&mut self) -> Result<(), Box<dyn Error>> {
+        self.subscribe(&RequestStream::GetKeepAlive(GetKeepAlive {})).await
+    }
+
+    pub async fn subscribe_profits(&mut self) -> Result<(), Box<dyn Error>> {
+        self.subscribe(&RequestStream::GetProfits(GetProfits {})).await
+    }
+
+    pub async fn subscribe_trades(&mut self) -> Result<(), Box<dyn Error>> {
+        self.subscribe(&RequestStream::GetTrades(GetTrades {})).await
+    }
+
+    pub async fn subscribe_trade_status(&mut self) -> Result<(), Box<dyn Error>> {
+        self.subscribe(&RequestStream::GetTradeStatus(GetTradeStatus {})).await
+    }
+
+    pub async fn next_event(&mut self) -> Result<ResponseStream, Box<dyn Error>> {
+        self.stream.response_stream().await
+    }
+}
@@ -0,0 +1,583 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::xapi_definitions::commands_common::{Cmd, Type, State, RequestStatus};
+use crate::xapi_definitions::commands_main::TradeTransInfo;
+use crate::xapi_definitions::commands_stream::*;
+
+pub const DEFAULT_CONTRACT_SIZE: f64 = 100_000.0;
+pub const DEFAULT_LEVERAGE: f64 = 30.0;
+
+/// Adverse price shift applied to every fill.
+pub trait SlippageModel: Send {
+    fn slippage(&mut self, cmd: Cmd, volume: f64, price: f64) -> f64;
+}
+
+pub struct NoSlippage;
+
+impl SlippageModel for NoSlippage {
+    fn slippage(&mut self, _cmd: Cmd, _volume: f64, _price: f64) -> f64 {
+        0.0
+    }
+}
+
+pub struct FixedSlippage(pub f64);
+
+impl SlippageModel for FixedSlippage {
+    fn slippage(&mut self, _cmd: Cmd, _volume: f64, _price: f64) -> f64 {
+        self.0
+    }
+}
+
+/// Commission charged when a position is opened.
+pub trait CommissionModel: Send {
+    fn commission(&self, volume: f64, notional: f64) -> f64;
+}
+
+pub struct NoCommission;
+
+impl CommissionModel for NoCommission {
+    fn commission(&self, _volume: f64, _notional: f64) -> f64 {
+        0.0
+    }
+}
+
+pub struct PerLotCommission(pub f64);
+
+impl CommissionModel for PerLotCommission {
+    fn commission(&self, volume: f64, _notional: f64) -> f64 {
+        self.0 * volume
+    }
+}
+
+pub struct PercentCommission(pub f64);
+
+impl CommissionModel for PercentCommission {
+    fn commission(&self, _volume: f64, notional: f64) -> f64 {
+        notional * self.0 / 100.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Quote {
+    pub symbol: String,
+    pub bid: f64,
+    pub ask: f64,
+    pub time: i64,
+}
+
+impl Quote {
+    pub fn from_tick(tick: &GetTickPricesResponse) -> Self {
+        Self {
+            symbol: tick.symbol.clone(),
+            bid: tick.bid as f64,
+            ask: tick.ask as f64,
+            time: tick.timestamp,
+        }
+    }
+}
+
+/// Open position or pending order.
+#[derive(Debug, Clone, Serialize)]
+pub struct SimOrder {
+    pub order: u32,
+    pub cmd: Cmd,
+    pub symbol: String,
+    pub volume: f64,
+    pub price: f64,
+    pub sl: f64,
+    pub tp: f64,
+    pub open_time: i64,
+    pub expiration: i64,
+    pub commission: f64,
+    pub custom_comment: Option<String>,
+}
+
+impl SimOrder {
+    pub fn is_buy(&self) -> bool {
+        matches!(self.cmd, Cmd::Buy | Cmd::BuyLimit | Cmd::BuyStop)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CloseReason {
+    Manual,
+    StopLoss,
+    TakeProfit,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClosedTrade {
+    pub position: u32,
+    pub symbol: String,
+    pub cmd: Cmd,
+    pub volume: f64,
+    pub open_time: i64,
+    pub open_price: f64,
+    pub close_time: i64,
+    pub close_price: f64,
+    pub profit: f64,
+    pub commission: f64,
+    pub reason: CloseReason,
+}
+
+/// Outcome of a submitted `TradeTransInfo` together with the stream messages it caused.
+#[derive(Debug)]
+pub struct Execution {
+    pub order: u32,
+    pub request_status: RequestStatus,
+    pub message: Option<String>,
+    pub price: f64,
+    pub events: Vec<ResponseStream>,
+}
+
+/// Local order matching against quotes, shared by the backtester, paper broker and mock server.
+pub struct SimulatedAccount {
+    balance: f64,
+    contract_size: f64,
+    leverage: f64,
+    /// Contract size and leverage of symbols that differ from the account defaults.
+    symbols: HashMap<String, (f64, f64)>,
+    next_order: u32,
+    positions: Vec<SimOrder>,
+    pending: Vec<SimOrder>,
+    closed: Vec<ClosedTrade>,
+    quotes: HashMap<String, Quote>,
+    slippage: Box<dyn SlippageModel>,
+    commission: Box<dyn CommissionModel>,
+}
+
+impl SimulatedAccount {
+    pub fn new(balance: f64) -> Self {
+        Self {
+            balance,
+            contract_size: DEFAULT_CONTRACT_SIZE,
+            leverage: DEFAULT_LEVERAGE,
+            symbols: HashMap::new(),
+            next_order: 1,
+            positions: Vec::new(),
+            pending: Vec::new(),
+            closed: Vec::new(),
+            quotes: HashMap::new(),
+            slippage: Box::new(NoSlippage),
+            commission: Box::new(NoCommission),
+        }
+    }
+
+    pub fn contract_size(mut self, contract_size: f64) -> Self {
+        self.contract_size = contract_size;
+        self
+    }
+
+    pub fn leverage(mut self, leverage: f64) -> Self {
+        self.leverage = leverage;
+        self
+    }
+
+    /// Contract size and leverage of `symbol`, other symbols use `contract_size` and `leverage`.
+    pub fn symbol_spec(mut self, symbol: &str, contract_size: f64, leverage: f64) -> Self {
+        self.symbols.insert(symbol.into(), (contract_size, leverage));
+        self
+    }
+
+    fn spec(&self, symbol: &str) -> (f64, f64) {
+        self.symbols.get(symbol).copied().unwrap_or((self.contract_size, self.leverage))
+    }
+
+    /// Number given to the first order, later ones count up from it.
+    pub fn first_order(mut self, order: u32) -> Self {
+        self.next_order = order;
+        self
+    }
+
+    pub fn slippage<M: SlippageModel + 'static>(mut self, slippage: M) -> Self {
+        self.slippage = Box::new(slippage);
+        self
+    }
+
+    pub fn commission<M: CommissionModel + 'static>(mut self, commission: M) -> Self {
+        self.commission = Box::new(commission);
+        self
+    }
+
+    pub fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    pub fn positions(&self) -> &[SimOrder] {
+        &self.positions
+    }
+
+    pub fn pending(&self) -> &[SimOrder] {
+        &self.pending
+    }
+
+    pub fn closed(&self) -> &[ClosedTrade] {
+        &self.closed
+    }
+
+    pub fn quote(&self, symbol: &str) -> Option<&Quote> {
+        self.quotes.get(symbol)
+    }
+
+    /// Price the position would be closed at right now.
+    pub fn close_price(&self, position: &SimOrder) -> Option<f64> {
+        self.quotes.get(&position.symbol)
+            .map(|quote| if position.is_buy() { quote.bid } else { quote.ask })
+    }
+
+    pub fn profit(&self, position: &SimOrder, close_price: f64) -> f64 {
+        let direction = if position.is_buy() { 1.0 } else { -1.0 };
+        (close_price - position.price) * direction * position.volume * self.spec(&position.symbol).0
+    }
+
+    pub fn floating_profit(&self) -> f64 {
+        self.positions.iter()
+            .filter_map(|position| self.close_price(position).map(|price| self.profit(position, price)))
+            .sum()
+    }
+
+    pub fn equity(&self) -> f64 {
+        self.balance + self.floating_profit()
+    }
+
+    pub fn margin(&self) -> f64 {
+        self.positions.iter()
+            .map(|position| {
+                let (contract_size, leverage) = self.spec(&position.symbol);
+                position.volume * contract_size * position.price / leverage
+            })
+            .sum()
+    }
+
+    /// Margin needed to open `volume` lots at the current ask.
+    pub fn margin_for(&self, symbol: &str, volume: f64) -> Option<f64> {
+        let (contract_size, leverage) = self.spec(symbol);
+        self.quotes.get(symbol).map(|quote| volume * contract_size * quote.ask / leverage)
+    }
+
+    pub fn balance_response(&self) -> GetBalanceResponse {
+        let equity = self.equity();
+        let margin = self.margin();
+        GetBalanceResponse {
+            balance: self.balance as f32,
+            credit: 0.0,
+            equity: equity as f32,
+            margin: margin as f32,
+            margin_free: (equity - margin) as f32,
+            margin_level: if margin > 0.0 { (equity / margin * 100.0) as f32 } else { 0.0 },
+            extra: Default::default(),
+        }
+    }
+
+    pub fn trade_response(&self, order: &SimOrder, r#type: Type, state: State) -> GetTradesReponse {
+        let close_price = self.close_price(order).unwrap_or(order.price);
+        let profit = match r#type {
+            Type::Open => Some(self.profit(order, close_price) as f32),
+            _ => None,
+        };
+        GetTradesReponse {
+            close_price: close_price as f32,
+            close_time: None,
+            closed: false,
+            cmd: order.cmd,
+            comment: String::new(),
+            commission: order.commission as f32,
+            custom_comment: order.custom_comment.clone(),
+            digits: 5,
+            expiration: None,
+            margin_rate: 0.0,
+            offset: 0,
+            open_price: order.price as f32,
+            open_time: order.open_time,
+            order: order.order,
+            order2: order.order,
+            position: order.order,
+            profit,
+            sl: order.sl as f32,
+            state,
+            storage: 0.0,
+            symbol: order.symbol.clone(),
+            tp: order.tp as f32,
+            r#type,
+            volume: order.volume as f32,
+            extra: Default::default(),
+        }
+    }
+
+    pub fn profit_response(&self, position: &SimOrder) -> GetProfitsResponse {
+        let close_price = self.close_price(position).unwrap_or(position.price);
+        GetProfitsResponse {
+            order: position.order,
+            order2: position.order,
+            position: position.order,
+            profit: self.profit(position, close_price) as f32,
+            extra: Default::default(),
+        }
+    }
+
+    fn closed_response(&self, position: &SimOrder, closed: &ClosedTrade) -> GetTradesReponse {
+        let mut trade = self.trade_response(position, Type::Close, State::Modified);
+        trade.close_price = closed.close_price as f32;
+        trade.close_time = Some(closed.close_time);
+        trade.closed = true;
+        trade.profit = Some(closed.profit as f32);
+        trade.volume = closed.volume as f32;
+        trade
+    }
+
+    fn status_event(order: u32, request_status: RequestStatus, price: f64, message: Option<String>, custom_comment: Option<String>) -> ResponseStream {
+        ResponseStream::TradeStatus(GetResponse {
+            data: GetTradeStatusResponse {
+                custom_comment,
+                message,
+                order,
+                price: price as f32,
+                request_status,
+                extra: Default::default(),
+            },
+            extra: Default::default(),
+        })
+    }
+
+    fn trade_event(trade: GetTradesReponse) -> ResponseStream {
+        ResponseStream::Trade(GetResponse { data: trade, extra: Default::default() })
+    }
+
+    fn balance_event(&self) -> ResponseStream {
+        ResponseStream::Balance(GetResponse { data: self.balance_response(), extra: Default::default() })
+    }
+
+    /// Executes a trade request the way the xAPI server would.
+    pub fn submit(&mut self, info: &TradeTransInfo, time: i64) -> Execution {
+        let result = match info.r#type {
+            Type::Open | Type::Pending => match info.cmd {
+                Cmd::Buy | Cmd::Sell => self.open_market(info, time),
+                Cmd::BuyLimit | Cmd::SellLimit | Cmd::BuyStop | Cmd::SellStop => self.open_pending(info, time),
+                Cmd::Balance | Cmd::Credit | Cmd::Unknown(_) => Err(format!("Operation {:?} is not a trade", info.cmd)),
+            },
+            Type::Close => self.close_request(info, time),
+            Type::Modify => self.modify(info),
+            Type::Delete => self.delete(info.order),
+            Type::Unknown(code) => Err(format!("Unknown transaction type {}", code)),
+        };
+
+        match result {
+            Ok((order, price, mut events)) => {
+                events.insert(0, Self::status_event(order, RequestStatus::Accepted, price, None, info.custom_comment.clone()));
+                events.push(self.balance_event());
+                Execution { order, request_status: RequestStatus::Accepted, message: None, price, events }
+            }
+            Err(message) => {
+                let events = vec![Self::status_event(info.order, RequestStatus::Rejected, 0.0, Some(message.clone()), info.custom_comment.clone())];
+                Execution { order: info.order, request_status: RequestStatus::Rejected, message: Some(message), price: 0.0, events }
+            }
+        }
+    }
+
+    fn next_order(&mut self) -> u32 {
+        let order = self.next_order;
+        self.next_order += 1;
+        order
+    }
+
+    fn fill_price(&mut self, buy: bool, quote: &Quote, volume: f64) -> f64 {
+        if buy {
+            quote.ask + self.slippage.slippage(Cmd::Buy, volume, quote.ask)
+        } else {
+            quote.bid - self.slippage.slippage(Cmd::Sell, volume, quote.bid)
+        }
+    }
+
+    fn open_position(&mut self, mut order: SimOrder, quote: &Quote) -> (u32, f64, Vec<ResponseStream>) {
+        let buy = order.is_buy();
+        order.cmd = if buy { Cmd::Buy } else { Cmd::Sell };
+        order.price = self.fill_price(buy, quote, order.volume);
+        order.open_time = quote.time;
+        order.commission = self.commission.commission(order.volume, order.volume * self.spec(&order.symbol).0 * order.price);
+        self.balance -= order.commission;
+
+        let event = Self::trade_event(self.trade_response(&order, Type::Open, State::Modified));
+        let result = (order.order, order.price, vec![event]);
+        self.positions.push(order);
+        result
+    }
+
+    fn new_order(&mut self, info: &TradeTransInfo, time: i64) -> Result<SimOrder, String> {
+        if info.volume <= 0.0 {
+            return Err("Invalid volume".into());
+        }
+        Ok(SimOrder {
+            order: self.next_order(),
+            cmd: info.cmd,
+            symbol: info.symbol.clone(),
+            volume: info.volume as f64,
+            price: info.price as f64,
+            sl: info.sl as f64,
+            tp: info.tp as f64,
+            open_time: time,
+            expiration: info.expiration as i64,
+            commission: 0.0,
+            custom_comment: info.custom_comment.clone(),
+        })
+    }
+
+    fn open_market(&mut self, info: &TradeTransInfo, time: i64) -> Result<(u32, f64, Vec<ResponseStream>), String> {
+        let quote = self.quotes.get(&info.symbol).cloned()
+            .ok_or_else(|| format!("No quotes for symbol {}", info.symbol))?;
+        let order = self.new_order(info, time)?;
+        Ok(self.open_position(order, &quote))
+    }
+
+    fn open_pending(&mut self, info: &TradeTransInfo, time: i64) -> Result<(u32, f64, Vec<ResponseStream>), String> {
+        if info.price <= 0.0 {
+            return Err("Invalid price".into());
+        }
+        let order = self.new_order(info, time)?;
+        let event = Self::trade_event(self.trade_response(&order, Type::Pending, State::Modified));
+        let result = (order.order, order.price, vec![event]);
+        self.pending.push(order);
+        Ok(result)
+    }
+
+    fn close_request(&mut self, info: &TradeTransInfo, time: i64) -> Result<(u32, f64, Vec<ResponseStream>), String> {
+        let index = self.positions.iter().position(|position| position.order == info.order)
+            .ok_or_else(|| format!("Position {} not found", info.order))?;
+        let quote = self.quotes.get(&self.positions[index].symbol).cloned()
+            .ok_or_else(|| format!("No quotes for symbol {}", self.positions[index].symbol))?;
+        let quote = Quote { time, ..quote };
+        Ok(self.close_position(index, info.volume as f64, &quote, CloseReason::Manual))
+    }
+
+    fn close_position(&mut self, index: usize, volume: f64, quote: &Quote, reason: CloseReason) -> (u32, f64, Vec<ResponseStream>) {
+        let position = self.positions[index].clone();
+        let volume = if volume <= 0.0 || volume >= position.volume { position.volume } else { volume };
+        let close_price = self.fill_price(!position.is_buy(), quote, volume);
+        let profit = self.profit(&SimOrder { volume, ..position.clone() }, close_price);
+        self.balance += profit;
+
+        let closed = ClosedTrade {
+            position: position.order,
+            symbol: position.symbol.clone(),
+            cmd: position.cmd,
+            volume,
+            open_time: position.open_time,
+            open_price: position.price,
+            close_time: quote.time,
+            close_price,
+            profit,
+            commission: if volume == position.volume { position.commission } else { 0.0 },
+            reason,
+        };
+
+        let mut events = vec![Self::trade_event(self.closed_response(&position, &closed))];
+        if volume < position.volume {
+            self.positions[index].volume -= volume;
+            events.push(Self::trade_event(self.trade_response(&self.positions[index], Type::Open, State::Modified)));
+        } else {
+            self.positions.remove(index);
+        }
+        self.closed.push(closed);
+
+        (position.order, close_price, events)
+    }
+
+    fn modify(&mut self, info: &TradeTransInfo) -> Result<(u32, f64, Vec<ResponseStream>), String> {
+        if let Some(position) = self.positions.iter_mut().find(|position| position.order == info.order) {
+            position.sl = info.sl as f64;
+            position.tp = info.tp as f64;
+            let position = position.clone();
+            let event = Self::trade_event(self.trade_response(&position, Type::Open, State::Modified));
+            return Ok((position.order, position.price, vec![event]));
+        }
+        if let Some(order) = self.pending.iter_mut().find(|order| order.order == info.order) {
+            if info.price > 0.0 {
+                order.price = info.price as f64;
+            }
+            order.sl = info.sl as f64;
+            order.tp = info.tp as f64;
+            order.expiration = info.expiration as i64;
+            let order = order.clone();
+            let event = Self::trade_event(self.trade_response(&order, Type::Pending, State::Modified));
+            return Ok((order.order, order.price, vec![event]));
+        }
+        Err(format!("Order {} not found", info.order))
+    }
+
+    fn delete(&mut self, order: u32) -> Result<(u32, f64, Vec<ResponseStream>), String> {
+        let index = self.pending.iter().position(|pending| pending.order == order)
+            .ok_or_else(|| format!("Pending order {} not found", order))?;
+        let order = self.pending.remove(index);
+        let event = Self::trade_event(self.trade_response(&order, Type::Delete, State::Deleted));
+        Ok((order.order, order.price, vec![event]))
+    }
+
+    fn pending_triggered(order: &SimOrder, quote: &Quote) -> bool {
+        match order.cmd {
+            Cmd::BuyLimit => quote.ask <= order.price,
+            Cmd::SellLimit => quote.bid >= order.price,
+            Cmd::BuyStop => quote.ask >= order.price,
+            Cmd::SellStop => quote.bid <= order.price,
+            _ => false,
+        }
+    }
+
+    fn exit_reason(position: &SimOrder, quote: &Quote) -> Option<CloseReason> {
+        let (price, stop_hit, profit_hit) = if position.is_buy() {
+            (quote.bid, quote.bid <= position.sl, quote.bid >= position.tp)
+        } else {
+            (quote.ask, quote.ask >= position.sl, quote.ask <= position.tp)
+        };
+        if price <= 0.0 {
+            None
+        } else if position.sl > 0.0 && stop_hit {
+            Some(CloseReason::StopLoss)
+        } else if position.tp > 0.0 && profit_hit {
+            Some(CloseReason::TakeProfit)
+        } else {
+            None
+        }
+    }
+
+    /// Feeds a new price, triggering pending orders, expirations and SL/TP.
+    pub fn on_quote(&mut self, quote: Quote) -> Vec<ResponseStream> {
+        self.quotes.insert(quote.symbol.clone(), quote.clone());
+        let mut events = Vec::new();
+
+        let mut index = 0;
+        while index < self.pending.len() {
+            let order = &self.pending[index];
+            if order.symbol != quote.symbol {
+                index += 1;
+            } else if order.expiration > 0 && quote.time >= order.expiration {
+                let order = self.pending.remove(index);
+                events.push(Self::trade_event(self.trade_response(&order, Type::Delete, State::Deleted)));
+            } else if Self::pending_triggered(order, &quote) {
+                let order = self.pending.remove(index);
+                let (order, price, trade_events) = self.open_position(order, &quote);
+                events.push(Self::status_event(order, RequestStatus::Accepted, price, None, None));
+                events.extend(trade_events);
+            } else {
+                index += 1;
+            }
+        }
+
+        let mut index = 0;
+        while index < self.positions.len() {
+            let position = &self.positions[index];
+            match Self::exit_reason(position, &quote).filter(|_| position.symbol == quote.symbol) {
+                Some(reason) => {
+                    let (_, _, trade_events) = self.close_position(index, 0.0, &quote, reason);
+                    events.extend(trade_events);
+                }
+                None => index += 1,
+            }
+        }
+
+        if !events.is_empty() {
+            events.push(self.balance_event());
+        }
+        events
+    }
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::history::Candle;
+use crate::xapi_definitions::commands_common::Period;
+
+const CSV_HEADER: &str = "ctm,open,high,low,close,vol";
+
+pub trait CandleStore {
+    /// Open time of the newest stored candle.
+    fn last_ctm(&self) -> Result<Option<i64>, Box<dyn Error>>;
+    /// Appends candles newer than `last_ctm`, older ones are skipped.
+    fn append(&mut self, candles: &[Candle]) -> Result<usize, Box<dyn Error>>;
+    fn load(&self) -> Result<Vec<Candle>, Box<dyn Error>>;
+}
+
+pub struct CsvStore {
+    path: PathBuf,
+    /// `last_ctm` once read, kept current by `append` so chunks don't re-read the file.
+    last_ctm: Option<Option<i64>>,
+}
+
+impl CsvStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self { path: path.as_ref().to_path_buf(), last_ctm: None }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn parse_line(line: &str) -> Result<Candle, Box<dyn Error>> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != 6 {
+            return Err(format!("Invalid candle record: {}", line))?;
+        }
+        Ok(Candle {
+            ctm: fields[0].parse()?,
+            open: fields[1].parse()?,
+            high: fields[2].parse()?,
+            low: fields[3].parse()?,
+            close: fields[4].parse()?,
+            vol: fields[5].parse()?,
+        })
+    }
+
+    fn records(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let reader = BufReader::new(File::open(&self.path)?);
+        let mut records = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line == CSV_HEADER {
+                continue;
+            }
+            records.push(line.to_string());
+        }
+        Ok(records)
+    }
+}
+
+impl CandleStore for CsvStore {
+    fn last_ctm(&self) -> Result<Option<i64>, Box<dyn Error>> {
+        if let Some(last_ctm) = self.last_ctm {
+            return Ok(last_ctm);
+        }
+        match self.records()?.last() {
+            Some(line) => Ok(Some(Self::parse_line(line)?.ctm)),
+            None => Ok(None),
+        }
+    }
+
+    fn append(&mut self, candles: &[Candle]) -> Result<usize, Box<dyn Error>> {
+        let last_ctm = self.last_ctm()?;
+        let write_header = !self.path.exists() || fs::metadata(&self.path)?.len() == 0;
+
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut writer = BufWriter::new(file);
+        if write_header {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+
+        let mut written = 0;
+        let mut last = last_ctm;
+        for candle in candles {
+            if last.is_some_and(|last| candle.ctm <= last) {
+                continue;
+            }
+            writeln!(writer, "{},{},{},{},{},{}"
+                , candle.ctm, candle.open, candle.high, candle.low, candle.close, candle.vol)?;
+            last = Some(candle.ctm);
+            written += 1;
+        }
+        writer.flush()?;
+        self.last_ctm = Some(last);
+
+        Ok(written)
+    }
+
+    fn load(&self) -> Result<Vec<Candle>, Box<dyn Error>> {
+        self.records()?.iter().map(|line| Self::parse_line(line)).collect()
+    }
+}
+
+/// Directory keeping one CSV series per symbol and period.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(Self { root: root.as_ref().to_path_buf() })
+    }
+
+    pub fn series(&self, symbol: &str, period: Period) -> CsvStore {
+        let file_name = format!("{}_{}.csv", symbol.replace(['/', '\\'], "_"), period.minutes());
+        CsvStore::new(self.root.join(file_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candle(ctm: i64) -> Candle {
+        Candle { ctm, open: 1.0, high: 2.0, low: 0.5, close: 1.5, vol: 10.0 }
+    }
+
+    #[test]
+    fn append_skips_candles_up_to_the_last_stored_one() {
+        let path = std::env::temp_dir().join(format!("xtb-store-{}.csv", uuid::Uuid::new_v4()));
+        let mut store = CsvStore::new(&path);
+        assert_eq!(store.append(&[candle(1), candle(2)]).unwrap(), 2);
+        assert_eq!(store.last_ctm().unwrap(), Some(2));
+        assert_eq!(store.append(&[candle(2), candle(3)]).unwrap(), 1);
+        assert_eq!(store.append(&[candle(1)]).unwrap(), 0);
+
+        // A fresh store reads the same series back from the file.
+        let reopened = CsvStore::new(&path);
+        assert_eq!(reopened.last_ctm().unwrap(), Some(3));
+        assert_eq!(reopened.load().unwrap().iter().map(|c| c.ctm).collect::<Vec<_>>(), [1, 2, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+}
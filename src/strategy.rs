@@ -0,0 +1,86 @@
+use crate::xapi_definitions::commands_common::{Cmd, Type};
+use crate::xapi_definitions::commands_main::TradeTransInfo;
+use crate::xapi_definitions::commands_stream::*;
+
+/// Trading logic driven by stream events. Orders are queued in `Orders` and executed
+/// by whatever runs the strategy (backtester, paper broker or live client).
+pub trait Strategy {
+    fn on_tick(&mut self, _tick: &GetTickPricesResponse, _orders: &mut Orders) {}
+    fn on_candle(&mut self, _candle: &GetCandlesResponse, _orders: &mut Orders) {}
+    fn on_trade(&mut self, _trade: &GetTradesReponse, _orders: &mut Orders) {}
+    fn on_trade_status(&mut self, _status: &GetTradeStatusResponse, _orders: &mut Orders) {}
+    fn on_balance(&mut self, _balance: &GetBalanceResponse, _orders: &mut Orders) {}
+    fn on_profit(&mut self, _profit: &GetProfitsResponse, _orders: &mut Orders) {}
+}
+
+/// Routes a stream message to the matching `Strategy` callback.
+pub fn dispatch<S: Strategy + ?Sized>(strategy: &mut S, event: &ResponseStream, orders: &mut Orders) {
+    match event {
+        ResponseStream::TickPrices(tick) => strategy.on_tick(&tick.data, orders),
+        ResponseStream::Candle(candle) => strategy.on_candle(&candle.data, orders),
+        ResponseStream::Trade(trade) => strategy.on_trade(&trade.data, orders),
+        ResponseStream::TradeStatus(status) => strategy.on_trade_status(&status.data, orders),
+        ResponseStream::Balance(balance) => strategy.on_balance(&balance.data, orders),
+        ResponseStream::Profit(profit) => strategy.on_profit(&profit.data, orders),
+        ResponseStream::KeepAlive(_) | ResponseStream::Unknown(_) => {}
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Orders {
+    queue: Vec<TradeTransInfo>,
+}
+
+impl Orders {
+    pub fn submit(&mut self, trade_trans_info: TradeTransInfo) {
+        self.queue.push(trade_trans_info);
+    }
+
+    pub fn open(&mut self, symbol: &str, cmd: Cmd, volume: f32, price: f32, sl: f32, tp: f32) {
+        self.submit(TradeTransInfo {
+            cmd,
+            custom_comment: None,
+            expiration: 0,
+            offset: 0,
+            order: 0,
+            price,
+            sl,
+            symbol: symbol.into(),
+            tp,
+            r#type: Type::Open,
+            volume,
+        });
+    }
+
+    pub fn buy(&mut self, symbol: &str, volume: f32) {
+        self.open(symbol, Cmd::Buy, volume, 0.0, 0.0, 0.0);
+    }
+
+    pub fn sell(&mut self, symbol: &str, volume: f32) {
+        self.open(symbol, Cmd::Sell, volume, 0.0, 0.0, 0.0);
+    }
+
+    pub fn close(&mut self, symbol: &str, position: u32, cmd: Cmd, volume: f32) {
+        self.submit(TradeTransInfo {
+            cmd,
+            custom_comment: None,
+            expiration: 0,
+            offset: 0,
+            order: position,
+            price: 0.0,
+            sl: 0.0,
+            symbol: symbol.into(),
+            tp: 0.0,
+            r#type: Type::Close,
+            volume,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn drain(&mut self) -> Vec<TradeTransInfo> {
+        std::mem::take(&mut self.queue)
+    }
+}
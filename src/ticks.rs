@@ -0,0 +1,414 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::timestamp_to_datetime;
+use crate::xapi_definitions::commands_stream::{GetCandlesResponse, GetTickPricesResponse, ResponseStream};
+
+/// Compact handle of a symbol name interned in a `SymbolTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(u32);
+
+impl SymbolId {
+    /// Position in the table, ids are dense from 0 so they can index a `Vec`.
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Symbol names seen on the stream, each stored once.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    ids: HashMap<Box<str>, SymbolId>,
+    names: Vec<Box<str>>,
+}
+
+impl SymbolTable {
+    /// Id of `symbol`, allocating only the first time it is seen.
+    pub fn intern(&mut self, symbol: &str) -> SymbolId {
+        if let Some(id) = self.ids.get(symbol) {
+            return *id;
+        }
+        let id = SymbolId(self.names.len() as u32);
+        self.names.push(symbol.into());
+        self.ids.insert(symbol.into(), id);
+        id
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<SymbolId> {
+        self.ids.get(symbol).copied()
+    }
+
+    /// Name of `id`. Panics if `id` comes from another table.
+    pub fn name(&self, id: SymbolId) -> &str {
+        &self.names[id.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// `GetTickPricesResponse` with the symbol interned, `Copy` and without allocations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tick {
+    pub ask: f32,
+    pub ask_volume: i32,
+    pub bid: f32,
+    pub bid_volume: i32,
+    pub high: f32,
+    pub level: i32,
+    pub low: f32,
+    pub quote_id: i32,
+    pub spread_raw: f32,
+    pub spread_table: f32,
+    pub symbol: SymbolId,
+    pub timestamp: i64,
+}
+
+impl Tick {
+    pub fn to_response(&self, symbols: &SymbolTable) -> GetTickPricesResponse {
+        GetTickPricesResponse {
+            ask: self.ask,
+            ask_volume: self.ask_volume,
+            bid: self.bid,
+            bid_volume: self.bid_volume,
+            high: self.high,
+            level: self.level,
+            low: self.low,
+            quote_id: self.quote_id,
+            spread_raw: self.spread_raw,
+            spread_table: self.spread_table,
+            symbol: symbols.name(self.symbol).into(),
+            timestamp: self.timestamp,
+            extra: Default::default(),
+        }
+    }
+}
+
+/// `GetCandlesResponse` with the symbol interned. `ctmString` is skipped, it can be
+/// rebuilt from `ctm`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub close: f32,
+    pub ctm: i64,
+    pub high: f32,
+    pub low: f32,
+    pub open: f32,
+    pub quote_id: i32,
+    pub symbol: SymbolId,
+    pub vol: f32,
+}
+
+impl Candle {
+    pub fn to_response(&self, symbols: &SymbolTable) -> GetCandlesResponse {
+        GetCandlesResponse {
+            close: self.close,
+            ctm: self.ctm,
+            ctm_string: timestamp_to_datetime(self.ctm),
+            high: self.high,
+            low: self.low,
+            open: self.open,
+            quote_id: self.quote_id,
+            symbol: symbols.name(self.symbol).into(),
+            vol: self.vol,
+            extra: Default::default(),
+        }
+    }
+}
+
+/// Stream message parsed by `TickParser`.
+#[derive(Debug, Clone)]
+pub enum StreamMessage {
+    Tick(Tick),
+    Candle(Candle),
+    /// Any other message, parsed as usual.
+    Other(Box<ResponseStream>),
+}
+
+impl StreamMessage {
+    pub fn command(&self) -> &str {
+        match self {
+            StreamMessage::Tick(_) => "tickPrices",
+            StreamMessage::Candle(_) => "candle",
+            StreamMessage::Other(message) => message.command(),
+        }
+    }
+
+    pub fn symbol<'a>(&'a self, symbols: &'a SymbolTable) -> Option<&'a str> {
+        match self {
+            StreamMessage::Tick(tick) => Some(symbols.name(tick.symbol)),
+            StreamMessage::Candle(candle) => Some(symbols.name(candle.symbol)),
+            StreamMessage::Other(message) => message.symbol(),
+        }
+    }
+}
+
+/// Parses stream frames with ticks and candles on a path that borrows from the frame
+/// and allocates nothing once every symbol has been seen. Other messages fall back to
+/// `ResponseStream`. With the `simd-json` feature the hot path uses SIMD JSON parsing,
+/// which still allocates its tape once per frame.
+#[derive(Default)]
+pub struct TickParser {
+    symbols: SymbolTable,
+    /// Copy of frames `simd-json` would rewrite, see `parse_in_place`.
+    #[cfg(feature = "simd-json")]
+    scratch: Vec<u8>,
+    #[cfg(feature = "simd-json")]
+    buffers: simd_json::Buffers,
+}
+
+impl TickParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// For interning the subscribed symbols up front.
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    pub fn parse(&mut self, frame: &str) -> Result<StreamMessage, Box<dyn Error>> {
+        #[cfg(feature = "simd-json")]
+        let hot = {
+            self.scratch.clear();
+            self.scratch.extend_from_slice(frame.as_bytes());
+            simd_json::serde::from_slice_with_buffers::<Hot>(&mut self.scratch, &mut self.buffers).ok()
+        };
+        #[cfg(not(feature = "simd-json"))]
+        let hot = serde_json::from_str::<Hot>(frame).ok();
+
+        match Self::intern(&mut self.symbols, hot) {
+            Some(message) => Ok(message),
+            None => Self::fallback(frame),
+        }
+    }
+
+    /// Like `parse` for a frame in the caller's buffer, e.g. `Connection`'s. With
+    /// `simd-json` the frame is parsed where it is instead of being copied, and its
+    /// bytes are the same afterwards.
+    pub fn parse_in_place(&mut self, frame: &mut [u8]) -> Result<StreamMessage, Box<dyn Error>> {
+        // simd-json writes into its input only to unescape strings, so a frame without
+        // a backslash stays intact for the fallback. Others are parsed from a copy.
+        #[cfg(feature = "simd-json")]
+        if !frame.contains(&b'\\') {
+            let hot = simd_json::serde::from_slice_with_buffers::<Hot>(frame, &mut self.buffers).ok();
+            return match Self::intern(&mut self.symbols, hot) {
+                Some(message) => Ok(message),
+                None => Self::fallback(std::str::from_utf8(frame)?),
+            };
+        }
+        self.parse(std::str::from_utf8(frame)?)
+    }
+
+    fn intern(symbols: &mut SymbolTable, hot: Option<Hot>) -> Option<StreamMessage> {
+        match hot? {
+            Hot::Tick(tick) => Some(StreamMessage::Tick(tick.intern(symbols))),
+            Hot::Candle(candle) => Some(StreamMessage::Candle(candle.intern(symbols))),
+            Hot::Other => None,
+        }
+    }
+
+    /// Malformed ticks are parsed again for the error naming the failing field.
+    fn fallback(frame: &str) -> Result<StreamMessage, Box<dyn Error>> {
+        Ok(StreamMessage::Other(Box::new(serde_json::from_str(frame)?)))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawTick<'a> {
+    ask: f32,
+    ask_volume: i32,
+    bid: f32,
+    bid_volume: i32,
+    high: f32,
+    level: i32,
+    low: f32,
+    quote_id: i32,
+    spread_raw: f32,
+    spread_table: f32,
+    #[serde(borrow)]
+    symbol: Cow<'a, str>,
+    timestamp: i64,
+}
+
+impl RawTick<'_> {
+    fn intern(self, symbols: &mut SymbolTable) -> Tick {
+        Tick {
+            ask: self.ask,
+            ask_volume: self.ask_volume,
+            bid: self.bid,
+            bid_volume: self.bid_volume,
+            high: self.high,
+            level: self.level,
+            low: self.low,
+            quote_id: self.quote_id,
+            spread_raw: self.spread_raw,
+            spread_table: self.spread_table,
+            symbol: symbols.intern(&self.symbol),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawCandle<'a> {
+    close: f32,
+    ctm: i64,
+    high: f32,
+    low: f32,
+    open: f32,
+    quote_id: i32,
+    #[serde(borrow)]
+    symbol: Cow<'a, str>,
+    vol: f32,
+}
+
+impl RawCandle<'_> {
+    fn intern(self, symbols: &mut SymbolTable) -> Candle {
+        Candle {
+            close: self.close,
+            ctm: self.ctm,
+            high: self.high,
+            low: self.low,
+            open: self.open,
+            quote_id: self.quote_id,
+            symbol: symbols.intern(&self.symbol),
+            vol: self.vol,
+        }
+    }
+}
+
+/// Message as seen by the hot path. `Other` also covers `data` sent before `command`,
+/// which only the fallback can handle.
+enum Hot<'a> {
+    Tick(RawTick<'a>),
+    Candle(RawCandle<'a>),
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "camelCase")]
+enum Field {
+    Command,
+    Data,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum HotCommand {
+    TickPrices,
+    Candle,
+    #[serde(other)]
+    Other,
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Hot<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HotVisitor<'a>(std::marker::PhantomData<Hot<'a>>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for HotVisitor<'a> {
+            type Value = Hot<'a>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a stream message")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut command = None;
+                let mut hot = Hot::Other;
+                while let Some(field) = map.next_key::<Field>()? {
+                    match (field, &command) {
+                        (Field::Command, _) => command = Some(map.next_value::<HotCommand>()?),
+                        (Field::Data, Some(HotCommand::TickPrices)) => hot = Hot::Tick(map.next_value()?),
+                        (Field::Data, Some(HotCommand::Candle)) => hot = Hot::Candle(map.next_value()?),
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(hot)
+            }
+        }
+
+        deserializer.deserialize_map(HotVisitor(std::marker::PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: &str = r#"{"command":"tickPrices","data":{"ask":1.0852,"askVolume":1000,"bid":1.085,"bidVolume":2000,"high":1.089,"level":0,"low":1.081,"quoteId":1,"spreadRaw":0.0002,"spreadTable":2.0,"symbol":"EURUSD","timestamp":1700000000000}}"#;
+
+    fn parse_in_place(parser: &mut TickParser, frame: &str) -> StreamMessage {
+        let mut bytes = frame.as_bytes().to_vec();
+        let message = parser.parse_in_place(&mut bytes).unwrap();
+        assert_eq!(bytes, frame.as_bytes());
+        message
+    }
+
+    #[test]
+    fn ticks_are_parsed_in_place_with_interned_symbols() {
+        let mut parser = TickParser::new();
+        let StreamMessage::Tick(first) = parse_in_place(&mut parser, TICK) else {
+            panic!("expected a tick");
+        };
+        let StreamMessage::Tick(second) = parser.parse(TICK).unwrap() else {
+            panic!("expected a tick");
+        };
+        assert_eq!(first, second);
+        assert_eq!(first.bid, 1.085);
+        assert_eq!(parser.symbols().name(first.symbol), "EURUSD");
+        assert_eq!(parser.symbols().len(), 1);
+    }
+
+    #[test]
+    fn candles_are_parsed_in_place_with_interned_symbols() {
+        let mut parser = TickParser::new();
+        parse_in_place(&mut parser, TICK);
+        let candle = r#"{"command":"candle","data":{"close":1.0851,"ctm":1700000040000,"ctmString":"Nov 14, 2023, 10:14:00 PM","high":1.0853,"low":1.0849,"open":1.085,"quoteId":1,"symbol":"EURUSD","vol":12.0}}"#;
+        let StreamMessage::Candle(candle) = parse_in_place(&mut parser, candle) else {
+            panic!("expected a candle");
+        };
+        assert_eq!(candle.ctm, 1_700_000_040_000);
+        assert_eq!(candle.open, 1.085);
+        assert_eq!(candle.close, 1.0851);
+        assert_eq!(candle.vol, 12.0);
+        assert_eq!(parser.symbols().name(candle.symbol), "EURUSD");
+        assert_eq!(parser.symbols().len(), 1);
+    }
+
+    #[test]
+    fn other_messages_fall_back_to_the_original_frame() {
+        let mut parser = TickParser::new();
+        let escaped = r#"{"command":"tradeStatus","data":{"customComment":"a\"b\\c","message":null,"order":7,"price":1.0,"requestStatus":3}}"#;
+        let StreamMessage::Other(message) = parse_in_place(&mut parser, escaped) else {
+            panic!("expected a fallback");
+        };
+        let ResponseStream::TradeStatus(status) = *message else {
+            panic!("expected a trade status");
+        };
+        assert_eq!(status.data.custom_comment.as_deref(), Some(r#"a"b\c"#));
+
+        let data_first = r#"{"data":{"timestamp":1700000000000},"command":"keepAlive"}"#;
+        assert!(matches!(parse_in_place(&mut parser, data_first), StreamMessage::Other(_)));
+        assert!(parser.parse_in_place(&mut b"{\"command\":\"tickPrices\",\"data\":{}}".to_vec()).is_err());
+    }
+}
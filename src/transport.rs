@@ -0,0 +1,256 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pki_types::pem::PemObject;
+use pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+
+mod proxy;
+
+pub use proxy::Proxy;
+
+/// Byte stream a connection runs on. Anything readable and writable works,
+/// e.g. a TLS stream, plain TCP or an in-memory pipe.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> AsyncStream for T {}
+
+/// SHA-256 of the SubjectPublicKeyInfo of `certificate`, the value `ConnectOptions::pin_spki_sha256` expects.
+pub fn spki_sha256(certificate: &CertificateDer<'_>) -> Result<[u8; 32], Box<dyn Error>> {
+    let certificate = webpki::EndEntityCert::try_from(certificate)
+        .map_err(|err| format!("Invalid certificate: {}", err))?;
+    Ok(Sha256::digest(certificate.subject_public_key_info().as_ref()).into())
+}
+
+/// How `XApiClient` reaches the server: TCP, then TLS unless plaintext.
+///
+/// ```no_run
+/// # fn options() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+/// use xtb::transport::ConnectOptions;
+///
+/// let options = ConnectOptions::new()
+///     .add_root_certificates_pem(&std::fs::read("ca.pem")?)?
+///     .server_name("xapi.internal")
+///     .connect_timeout(Duration::from_secs(5));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct ConnectOptions {
+    roots: Option<RootCertStore>,
+    pins: Vec<[u8; 32]>,
+    client_auth: Option<(Vec<CertificateDer<'static>>, Arc<PrivateKeyDer<'static>>)>,
+    server_name: Option<String>,
+    connect_timeout: Option<Duration>,
+    handshake_timeout: Option<Duration>,
+    plaintext: bool,
+    proxy: Option<Proxy>,
+}
+
+impl fmt::Debug for ConnectOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectOptions")
+            .field("custom_roots", &self.roots.as_ref().map(|roots| roots.len()))
+            .field("pins", &self.pins.len())
+            .field("client_auth", &self.client_auth.is_some())
+            .field("server_name", &self.server_name)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("plaintext", &self.plaintext)
+            .field("proxy", &self.proxy)
+            .finish()
+    }
+}
+
+impl ConnectOptions {
+    /// TLS trusting the webpki roots, no timeouts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts only `roots` instead of the webpki roots.
+    pub fn roots(mut self, roots: RootCertStore) -> Self {
+        self.roots = Some(roots);
+        self
+    }
+
+    /// Trusts the certificates in `pem` (e.g. a private CA) instead of the webpki roots.
+    pub fn add_root_certificates_pem(mut self, pem: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let roots = self.roots.get_or_insert_with(RootCertStore::empty);
+        for certificate in CertificateDer::pem_slice_iter(pem) {
+            roots.add(certificate?)?;
+        }
+        Ok(self)
+    }
+
+    /// Accepts the server only if a certificate of its chain has this SPKI hash,
+    /// on top of the normal validation. Can be given several times.
+    pub fn pin_spki_sha256(mut self, hash: [u8; 32]) -> Self {
+        self.pins.push(hash);
+        self
+    }
+
+    /// Presents a client certificate during the handshake.
+    pub fn client_certificate(mut self, chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+        self.client_auth = Some((chain, Arc::new(key)));
+        self
+    }
+
+    pub fn client_certificate_pem(self, chain: &[u8], key: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let chain = CertificateDer::pem_slice_iter(chain).collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_slice(key)?;
+        Ok(self.client_certificate(chain, key))
+    }
+
+    /// Name sent in SNI and checked against the certificate, instead of the host.
+    pub fn server_name(mut self, server_name: &str) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Plain TCP without TLS, for local servers such as `mock::MockServer`.
+    pub fn plaintext(mut self, plaintext: bool) -> Self {
+        self.plaintext = plaintext;
+        self
+    }
+
+    pub fn is_plaintext(&self) -> bool {
+        self.plaintext
+    }
+
+    /// Tunnels the connection through `proxy`. The TLS handshake with the server runs inside the tunnel.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Opens a stream to `host:port` with these options.
+    pub async fn open(&self, host: &str, port: &str) -> Result<Box<dyn AsyncStream>, Box<dyn Error>> {
+        let socket = match &self.proxy {
+            Some(proxy) => {
+                let port = port.parse().map_err(|_| format!("Invalid port {}", port))?;
+                with_timeout(self.connect_timeout, "Proxy connect", async {
+                    let mut socket = TcpStream::connect(proxy.address()).await?;
+                    proxy.tunnel(&mut socket, host, port).await?;
+                    debug!(proxy = proxy.address(), host, port, "proxy tunnel established");
+                    Ok::<_, Box<dyn Error>>(socket)
+                }).await??
+            }
+            None => {
+                let address = format!("{}:{}", host, port);
+                with_timeout(self.connect_timeout, "Connect", TcpStream::connect(address.as_str())).await??
+            }
+        };
+        if self.plaintext {
+            return Ok(Box::new(socket));
+        }
+        self.handshake(host, socket).await
+    }
+
+    /// Runs the TLS handshake on an already connected stream, e.g. a proxy tunnel.
+    pub async fn handshake<S: AsyncStream + 'static>(&self, host: &str, socket: S) -> Result<Box<dyn AsyncStream>, Box<dyn Error>> {
+        let server_name = ServerName::try_from(self.server_name.as_deref().unwrap_or(host))?.to_owned();
+        let connector = TlsConnector::from(Arc::new(self.tls_config()?));
+        let socket = with_timeout(self.handshake_timeout, "TLS handshake", connector.connect(server_name, socket)).await??;
+        debug!(host, "TLS established");
+        Ok(Box::new(socket))
+    }
+
+    fn tls_config(&self) -> Result<rustls::ClientConfig, Box<dyn Error>> {
+        let roots = self.roots.clone().unwrap_or_else(|| RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        });
+        let builder = if self.pins.is_empty() {
+            rustls::ClientConfig::builder().with_root_certificates(roots)
+        } else {
+            let verifier = PinnedVerifier {
+                inner: WebPkiServerVerifier::builder(Arc::new(roots)).build()?,
+                pins: self.pins.clone(),
+            };
+            rustls::ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+        };
+        Ok(match &self.client_auth {
+            Some((chain, key)) => builder.with_client_auth_cert(chain.clone(), key.clone_key())?,
+            None => builder.with_no_client_auth(),
+        })
+    }
+}
+
+async fn with_timeout<F: std::future::Future>(timeout: Option<Duration>, step: &str, future: F) -> Result<F::Output, Box<dyn Error>> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future).await
+            .map_err(|_| format!("{} timed out after {:?}", step, timeout).into()),
+        None => Ok(future.await),
+    }
+}
+
+/// Normal webpki validation plus a check that the chain contains a pinned key.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let pinned = std::iter::once(end_entity).chain(intermediates)
+            .filter_map(|certificate| spki_sha256(certificate).ok())
+            .any(|hash| self.pins.contains(&hash));
+        if !pinned {
+            return Err(rustls::Error::General("Server key does not match any pinned SPKI hash".into()));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,314 @@
+use std::error::Error;
+use std::net::IpAddr;
+
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use url::Url;
+
+use crate::secret::Secret;
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_USER_PASSWORD: u8 = 2;
+const SOCKS_NO_ACCEPTABLE: u8 = 0xff;
+const SOCKS_CONNECT: u8 = 1;
+/// Longest HTTP CONNECT reply head accepted.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Proxy the connections are tunnelled through. TLS to the xAPI host runs inside the tunnel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Proxy {
+    /// SOCKS5, with username/password authentication when `credentials` is set.
+    Socks5 { address: String, credentials: Option<(String, Secret)> },
+    /// HTTP `CONNECT`, with Basic authentication when `credentials` is set.
+    HttpConnect { address: String, credentials: Option<(String, Secret)> },
+}
+
+impl Proxy {
+    /// Parses `socks5://[user:password@]host:port` or `http://[user:password@]host:port`.
+    pub fn from_url(url: &str) -> Result<Self, Box<dyn Error>> {
+        let parsed = Url::parse(url).map_err(|err| format!("Invalid proxy URL {}: {}", url, err))?;
+        let host = parsed.host_str().ok_or_else(|| format!("Proxy URL {} has no host", url))?;
+        let default_port = match parsed.scheme() {
+            "socks5" | "socks5h" => 1080,
+            "http" => 8080,
+            scheme => Err(format!("Unsupported proxy scheme {}", scheme))?,
+        };
+        let address = format!("{}:{}", host, parsed.port().unwrap_or(default_port));
+        let credentials = (!parsed.username().is_empty()).then(|| {
+            let decode = |value: &str| percent_decode_str(value).decode_utf8_lossy().into_owned();
+            (decode(parsed.username()), Secret::new(decode(parsed.password().unwrap_or_default())))
+        });
+
+        Ok(match parsed.scheme() {
+            "http" => Proxy::HttpConnect { address, credentials },
+            _ => Proxy::Socks5 { address, credentials },
+        })
+    }
+
+    pub fn address(&self) -> &str {
+        match self {
+            Proxy::Socks5 { address, .. } | Proxy::HttpConnect { address, .. } => address,
+        }
+    }
+
+    /// Asks the proxy on `socket` for a tunnel to `host:port`.
+    pub async fn tunnel<S: AsyncRead + AsyncWrite + Unpin>(&self, socket: &mut S, host: &str, port: u16) -> Result<(), Box<dyn Error>> {
+        match self {
+            Proxy::Socks5 { credentials, .. } => socks5_connect(socket, host, port, credentials.as_ref()).await,
+            Proxy::HttpConnect { credentials, .. } => http_connect(socket, host, port, credentials.as_ref()).await,
+        }
+    }
+}
+
+async fn socks5_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&(String, Secret)>,
+) -> Result<(), Box<dyn Error>> {
+    let method = if credentials.is_some() { SOCKS_USER_PASSWORD } else { SOCKS_NO_AUTH };
+    socket.write_all(&[SOCKS_VERSION, 1, method]).await?;
+    let mut reply = [0u8; 2];
+    socket.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        Err("Proxy is not a SOCKS5 server")?;
+    }
+    match (reply[1], credentials) {
+        (SOCKS_NO_AUTH, _) => {}
+        (SOCKS_USER_PASSWORD, Some((user, password))) => {
+            let password = password.expose();
+            if user.len() > 255 || password.len() > 255 {
+                Err("SOCKS5 user and password are limited to 255 bytes")?;
+            }
+            let mut request = vec![1, user.len() as u8];
+            request.extend_from_slice(user.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            socket.write_all(&request).await?;
+            socket.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                Err("SOCKS5 proxy rejected the credentials")?;
+            }
+        }
+        (SOCKS_NO_ACCEPTABLE, _) => Err("SOCKS5 proxy accepts none of the offered authentication methods")?,
+        (method, _) => Err(format!("SOCKS5 proxy chose unsupported method {}", method))?,
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                Err("Host name too long for SOCKS5")?;
+            }
+            request.push(3);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    socket.write_all(&request).await?;
+
+    let mut head = [0u8; 4];
+    socket.read_exact(&mut head).await?;
+    if head[1] != 0 {
+        let reason = match head[1] {
+            1 => "general failure",
+            2 => "connection not allowed by ruleset",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "unknown error",
+        };
+        Err(format!("SOCKS5 proxy could not connect to {}:{}: {}", host, port, reason))?;
+    }
+    let bound_address = match head[3] {
+        1 => 4,
+        4 => 16,
+        3 => socket.read_u8().await? as usize,
+        atyp => Err(format!("SOCKS5 proxy sent unknown address type {}", atyp))?,
+    };
+    let mut bound = vec![0u8; bound_address + 2];
+    socket.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+async fn http_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<&(String, Secret)>,
+) -> Result<(), Box<dyn Error>> {
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some((user, password)) = credentials {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password.expose()));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    socket.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte so nothing after the reply head is taken from the tunnel.
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD {
+            Err("HTTP proxy reply head too long")?;
+        }
+        head.push(socket.read_u8().await?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if !status_line.starts_with("HTTP/1.") || status != "200" {
+        Err(format!("HTTP proxy refused CONNECT to {}: {}", authority, status_line))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `tunnel` against a peer that reads each expected request and answers it, then
+    /// hangs up. Returns the result and the client end for reading what follows the reply.
+    async fn tunnel(proxy: &Proxy, host: &str, port: u16, exchanges: Vec<(Vec<u8>, Vec<u8>)>) -> (Result<(), String>, tokio::io::DuplexStream) {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let peer = async move {
+            for (expected, reply) in exchanges {
+                let mut received = vec![0; expected.len()];
+                server.read_exact(&mut received).await.unwrap();
+                assert_eq!(String::from_utf8_lossy(&received), String::from_utf8_lossy(&expected));
+                server.write_all(&reply).await.unwrap();
+            }
+        };
+        let (result, ()) = tokio::join!(proxy.tunnel(&mut client, host, port), peer);
+        (result.map_err(|err| err.to_string()), client)
+    }
+
+    fn socks5(credentials: Option<(&str, &str)>) -> Proxy {
+        let credentials = credentials.map(|(user, password)| (user.to_string(), Secret::new(password)));
+        Proxy::Socks5 { address: "proxy:1080".into(), credentials }
+    }
+
+    fn http(credentials: Option<(&str, &str)>) -> Proxy {
+        let credentials = credentials.map(|(user, password)| (user.to_string(), Secret::new(password)));
+        Proxy::HttpConnect { address: "proxy:8080".into(), credentials }
+    }
+
+    fn bytes(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[tokio::test]
+    async fn socks5_connects_to_an_ipv4_address_without_auth() {
+        let (result, _) = tunnel(&socks5(None), "127.0.0.1", 5124, vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (vec![5, 1, 0, 1, 127, 0, 0, 1, 0x14, 0x04], vec![5, 0, 0, 1, 10, 0, 0, 1, 0x30, 0x39]),
+        ]).await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_authenticates_and_sends_host_names() {
+        let (result, _) = tunnel(&socks5(Some(("user", "secret"))), "xapi.xtb.com", 443, vec![
+            (vec![5, 1, 2], vec![5, 2]),
+            (bytes(&[&[1, 4], b"user", &[6], b"secret"]), vec![1, 0]),
+            (bytes(&[&[5, 1, 0, 3, 12], b"xapi.xtb.com", &[1, 187]]), bytes(&[&[5, 0, 0, 3, 5], b"proxy", &[0, 0]])),
+        ]).await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_sends_ipv6_addresses() {
+        let ipv6 = "::1".parse::<std::net::Ipv6Addr>().unwrap().octets();
+        let (result, _) = tunnel(&socks5(None), "::1", 443, vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (bytes(&[&[5, 1, 0, 4], &ipv6, &[1, 187]]), bytes(&[&[5, 0, 0, 4], &ipv6, &[1, 187]])),
+        ]).await;
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_reports_failed_negotiation() {
+        let (result, _) = tunnel(&socks5(None), "127.0.0.1", 443, vec![(vec![5, 1, 0], vec![4, 0])]).await;
+        assert!(result.unwrap_err().contains("not a SOCKS5 server"));
+
+        let (result, _) = tunnel(&socks5(None), "127.0.0.1", 443, vec![(vec![5, 1, 0], vec![5, SOCKS_NO_ACCEPTABLE])]).await;
+        assert!(result.unwrap_err().contains("accepts none"));
+
+        // Username/password chosen although only no authentication was offered.
+        let (result, _) = tunnel(&socks5(None), "127.0.0.1", 443, vec![(vec![5, 1, 0], vec![5, 2])]).await;
+        assert!(result.unwrap_err().contains("unsupported method 2"));
+
+        let (result, _) = tunnel(&socks5(Some(("user", "wrong"))), "127.0.0.1", 443, vec![
+            (vec![5, 1, 2], vec![5, 2]),
+            (bytes(&[&[1, 4], b"user", &[5], b"wrong"]), vec![1, 1]),
+        ]).await;
+        assert!(result.unwrap_err().contains("rejected the credentials"));
+    }
+
+    #[tokio::test]
+    async fn socks5_reports_connect_failures() {
+        let request = vec![5, 1, 0, 1, 127, 0, 0, 1, 1, 187];
+        let (result, _) = tunnel(&socks5(None), "127.0.0.1", 443, vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (request.clone(), vec![5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+        ]).await;
+        assert_eq!(result.unwrap_err(), "SOCKS5 proxy could not connect to 127.0.0.1:443: connection refused");
+
+        let (result, _) = tunnel(&socks5(None), "127.0.0.1", 443, vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (request, vec![5, 0, 0, 9]),
+        ]).await;
+        assert!(result.unwrap_err().contains("unknown address type 9"));
+    }
+
+    #[tokio::test]
+    async fn http_connect_leaves_bytes_after_the_reply_head_in_the_tunnel() {
+        let request = "CONNECT xapi.xtb.com:5124 HTTP/1.1\r\nHost: xapi.xtb.com:5124\r\n\r\n";
+        let reply = "HTTP/1.1 200 Connection established\r\nVia: proxy\r\n\r\nserver hello";
+        let (result, mut client) = tunnel(&http(None), "xapi.xtb.com", 5124, vec![(request.into(), reply.into())]).await;
+        result.unwrap();
+
+        let mut tunnelled = String::new();
+        client.read_to_string(&mut tunnelled).await.unwrap();
+        assert_eq!(tunnelled, "server hello");
+    }
+
+    #[tokio::test]
+    async fn http_connect_sends_basic_credentials_and_reports_407() {
+        let request = "CONNECT [::1]:443 HTTP/1.1\r\nHost: [::1]:443\r\nProxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n\r\n";
+        let reply = "HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic\r\n\r\n";
+        let (result, _) = tunnel(&http(Some(("user", "secret"))), "::1", 443, vec![(request.into(), reply.into())]).await;
+        assert_eq!(result.unwrap_err(), "HTTP proxy refused CONNECT to [::1]:443: HTTP/1.1 407 Proxy Authentication Required");
+    }
+
+    #[tokio::test]
+    async fn http_connect_rejects_malformed_replies() {
+        let request = "CONNECT host:443 HTTP/1.1\r\nHost: host:443\r\n\r\n";
+        let (result, _) = tunnel(&http(None), "host", 443, vec![(request.into(), "SSH-2.0-OpenSSH\r\n\r\n".into())]).await;
+        assert!(result.unwrap_err().contains("refused CONNECT"));
+
+        // Closed before the head ends.
+        let (result, _) = tunnel(&http(None), "host", 443, vec![(request.into(), "HTTP/1.1 200 OK\r\n".into())]).await;
+        assert!(result.is_err());
+
+        let (result, _) = tunnel(&http(None), "host", 443, vec![(request.into(), vec![b'x'; MAX_HEAD + 1])]).await;
+        assert!(result.unwrap_err().contains("too long"));
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::{ClientConfig, ConfigFile};
+use crate::secret::Secret;
+use crate::xapi_definitions::commands_main::Request;
+
+pub const VAULT_VERSION: u32 = 1;
+pub const PBKDF2_ROUNDS: u32 = 310_000;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Credential {
+    pub user_id: String,
+    pub password: Secret,
+}
+
+/// On-disk layout, only `ciphertext` holds credentials.
+#[derive(Debug, Deserialize, Serialize)]
+struct VaultFile {
+    version: u32,
+    rounds: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Credentials per config profile, encrypted with a key derived from a master passphrase.
+pub struct Vault {
+    path: PathBuf,
+    rounds: u32,
+    salt: Vec<u8>,
+    key: Option<[u8; KEY_LEN]>,
+    entries: BTreeMap<String, Credential>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Decoded from bytes, so a hand-edited file with non-ASCII text is an error and not a panic.
+    let digit = |byte: u8| (byte as char).to_digit(16).ok_or("Invalid hex string");
+    let hex = hex.as_bytes();
+    if !hex.len().is_multiple_of(2) {
+        Err("Invalid hex string")?;
+    }
+    hex.chunks(2)
+        .map(|pair| Ok((digit(pair[0])? << 4 | digit(pair[1])?) as u8))
+        .collect()
+}
+
+/// Replaces `path` with `contents` atomically: written to a temporary file only the
+/// owner can read, synced, then renamed over `path`. A crash leaves the old file intact.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let file_name = path.file_name().ok_or_else(|| format!("Invalid vault path {}", path.display()))?;
+    let mut suffix = [0; 8];
+    OsRng.fill_bytes(&mut suffix);
+    let temp = dir.join(format!(".{}.{}.tmp", file_name.to_string_lossy(), to_hex(&suffix)));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let result = options.open(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp);
+        Err(format!("Failed to write vault {}: {}", path.display(), err))?;
+    }
+    // Persists the rename itself.
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+impl Vault {
+    /// `vault.json` next to the default config file.
+    pub fn default_path() -> Option<PathBuf> {
+        ConfigFile::default_path()
+            .and_then(|path| path.parent().map(|dir| dir.join("vault.json")))
+    }
+
+    /// Creates an empty unlocked vault, it is written on `save`.
+    pub fn create<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self, Box<dyn Error>> {
+        if path.as_ref().exists() {
+            return Err(format!("Vault {} already exists", path.as_ref().display()))?;
+        }
+        let mut salt = vec![0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let mut vault = Self {
+            path: path.as_ref().to_path_buf(),
+            rounds: PBKDF2_ROUNDS,
+            salt,
+            key: None,
+            entries: BTreeMap::new(),
+        };
+        vault.key = Some(vault.derive_key(passphrase));
+        Ok(vault)
+    }
+
+    /// Opens a vault in the locked state.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let file = Self::read_file(path.as_ref())?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            rounds: file.rounds,
+            salt: from_hex(&file.salt)?,
+            key: None,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    pub fn open_unlocked<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self, Box<dyn Error>> {
+        let mut vault = Self::open(path)?;
+        vault.unlock(passphrase)?;
+        Ok(vault)
+    }
+
+    fn read_file(path: &Path) -> Result<VaultFile, Box<dyn Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read vault {}: {}", path.display(), err))?;
+        let file: VaultFile = serde_json::from_str(&content)?;
+        if file.version != VAULT_VERSION {
+            return Err(format!("Unsupported vault version {}", file.version))?;
+        }
+        Ok(file)
+    }
+
+    fn derive_key(&self, passphrase: &str) -> [u8; KEY_LEN] {
+        let mut key = [0; KEY_LEN];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &self.salt, self.rounds, &mut key);
+        key
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), Box<dyn Error>> {
+        let file = Self::read_file(&self.path)?;
+        let key = self.derive_key(passphrase);
+        let cipher = XChaCha20Poly1305::new(&key.into());
+        let nonce = from_hex(&file.nonce)?;
+        if nonce.len() != 24 {
+            return Err("Invalid vault nonce")?;
+        }
+        let mut plaintext = cipher
+            .decrypt(XNonce::from_slice(&nonce), from_hex(&file.ciphertext)?.as_slice())
+            .map_err(|_| "Wrong passphrase or corrupted vault")?;
+
+        let entries = serde_json::from_slice(&plaintext);
+        plaintext.fill(0);
+        self.entries = entries?;
+        self.key = Some(key);
+        Ok(())
+    }
+
+    /// Forgets the key and the decrypted credentials.
+    pub fn lock(&mut self) {
+        if let Some(key) = self.key.as_mut() {
+            key.fill(0);
+        }
+        self.key = None;
+        self.entries.clear();
+    }
+
+    fn key(&self) -> Result<&[u8; KEY_LEN], Box<dyn Error>> {
+        self.key.as_ref().ok_or_else(|| "Vault is locked".into())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let cipher = XChaCha20Poly1305::new(&(*self.key()?).into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut plaintext = serde_json::to_vec(&self.entries)?;
+        let ciphertext = cipher.encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| "Failed to encrypt vault");
+        plaintext.fill(0);
+
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            rounds: self.rounds,
+            salt: to_hex(&self.salt),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext?),
+        };
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        write_private(&self.path, serde_json::to_string_pretty(&file)?.as_bytes())
+    }
+
+    pub fn add(&mut self, profile: &str, user_id: &str, password: &str) -> Result<(), Box<dyn Error>> {
+        self.key()?;
+        self.entries.insert(profile.into(), Credential {
+            user_id: user_id.into(),
+            password: password.into(),
+        });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, profile: &str) -> Result<Credential, Box<dyn Error>> {
+        self.key()?;
+        self.entries.remove(profile)
+            .ok_or_else(|| format!("No credentials stored for profile {}", profile).into())
+    }
+
+    /// Profiles and their user ids.
+    pub fn list(&self) -> Result<Vec<(&str, &str)>, Box<dyn Error>> {
+        self.key()?;
+        Ok(self.entries.iter()
+            .map(|(profile, credential)| (profile.as_str(), credential.user_id.as_str()))
+            .collect())
+    }
+
+    pub fn get(&self, profile: &str) -> Result<Option<&Credential>, Box<dyn Error>> {
+        self.key()?;
+        Ok(self.entries.get(profile))
+    }
+
+    /// Builds the login request for the config profile from stored credentials.
+    pub fn login_request(&self, config: &ClientConfig) -> Result<Request, Box<dyn Error>> {
+        let credential = self.get(&config.profile)?
+            .ok_or_else(|| format!("No credentials stored for profile {}", config.profile))?;
+        Ok(config.login_request(&credential.user_id, credential.password.expose()))
+    }
+}
+
+impl Drop for Vault {
+    fn drop(&mut self) {
+        self.lock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut suffix = [0; 8];
+        OsRng.fill_bytes(&mut suffix);
+        std::env::temp_dir().join(format!("xtb-vault-{}-{}", to_hex(&suffix), name))
+    }
+
+    /// Unlocked vault at `path` with a fast key derivation.
+    fn vault(path: &Path) -> Vault {
+        let mut vault = Vault {
+            path: path.to_path_buf(),
+            rounds: 1,
+            salt: vec![7; SALT_LEN],
+            key: None,
+            entries: BTreeMap::new(),
+        };
+        vault.key = Some(vault.derive_key("passphrase"));
+        vault
+    }
+
+    #[test]
+    fn hex_round_trips_and_rejects_invalid_input() {
+        assert_eq!(from_hex(&to_hex(&[0, 1, 0xab, 0xff])).unwrap(), vec![0, 1, 0xab, 0xff]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(from_hex("aé").is_err());
+        assert!(from_hex("éé").is_err());
+    }
+
+    #[test]
+    fn save_replaces_the_vault_and_reopens() {
+        let path = temp_path("vault.json");
+        let mut first = vault(&path);
+        first.add("demo", "12345", "secret").unwrap();
+        first.save().unwrap();
+        first.add("real", "67890", "other").unwrap();
+        first.save().unwrap();
+
+        let reopened = Vault::open_unlocked(&path, "passphrase").unwrap();
+        assert_eq!(reopened.list().unwrap(), vec![("demo", "12345"), ("real", "67890")]);
+        assert!(Vault::open_unlocked(&path, "wrong").is_err());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let leftovers = fs::read_dir(path.parent().unwrap()).unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().contains(&*path.file_name().unwrap().to_string_lossy()))
+            .count();
+        assert_eq!(leftovers, 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -2,11 +2,14 @@ pub mod commands_common;
 pub mod commands_main;
 pub mod commands_stream;
 
+use serde::de::{self, Deserialize};
 use serde::Serialize;
+use serde_json::Value;
 use std::error::Error;
 
 use commands_main::Request;
 use commands_stream::RequestStream;
+use crate::secret::Secret;
 
 pub trait Execute
 where
@@ -17,6 +20,31 @@ where
         //println!("json_request: {:?}", json_request);
         Ok(json_request)
     }
+
+    /// Serialized request with `customTag` set, and the command name.
+    fn command_tagged(&self, custom_tag: &str) -> Result<(String, String), Box<dyn Error>> {
+        let mut json_request = serde_json::to_value(self)?;
+        let name = json_request["command"].as_str().unwrap_or_default().to_string();
+        if let Some(request) = json_request.as_object_mut() {
+            request.insert("customTag".into(), custom_tag.into());
+        }
+        Ok((serde_json::to_string(&json_request)?, name))
+    }
+
+    /// Serialized request with `streamSessionId` set, and the command name.
+    fn command_in_session(&self, stream_session_id: &Secret) -> Result<(String, String), Box<dyn Error>> {
+        let mut json_request = serde_json::to_value(self)?;
+        let name = json_request["command"].as_str().unwrap_or_default().to_string();
+        if let Some(request) = json_request.as_object_mut() {
+            request.insert("streamSessionId".into(), stream_session_id.expose().into());
+        }
+        Ok((serde_json::to_string(&json_request)?, name))
+    }
+}
+
+/// Deserializes `value`, naming the path of the failing field (e.g. `returnData.ask`) in the error.
+fn from_value_traced<'de, T: Deserialize<'de>, E: de::Error>(value: Value) -> Result<T, E> {
+    serde_path_to_error::deserialize(value).map_err(E::custom)
 }
 
 impl Execute for Request {}
@@ -1,41 +1,205 @@
-use serde::{Deserialize, Serialize};
-use serde_repr::{Serialize_repr, Deserialize_repr};
-
-#[derive(Debug, Deserialize_repr, Serialize_repr)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-#[repr(u8)]
-pub enum Cmd {
-    Buy = 0,
-    Sell = 1,
-    BuyLimit = 2,
-    SellLimit = 3,
-    BuyStop = 4,
-    SellStop = 5,
-    Balance = 6,
-    Credit = 7,
-}
-
-#[derive(Debug, Deserialize_repr, Serialize_repr)]
-#[repr(u8)]
-pub enum r#Type {
-    Open = 0,
-    Pending = 1,
-    Close = 2,
-    Modify = 3,
-    Delete = 4,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// Fields of a response this version does not know, kept instead of dropped.
+pub type Extra = Map<String, Value>;
+
+/// Integer-coded enum with an `Unknown` variant, so codes added by the server later
+/// do not fail the whole message.
+macro_rules! coded_enum {
+    ($(#[$meta:meta])* pub enum $name:ident { $($variant:ident = $code:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)*
+            Unknown(i64),
+        }
+
+        impl $name {
+            /// Value on the wire.
+            pub fn code(&self) -> i64 {
+                match self {
+                    $($name::$variant => $code,)*
+                    $name::Unknown(code) => *code,
+                }
+            }
+
+            pub fn from_code(code: i64) -> Self {
+                match code {
+                    $($code => $name::$variant,)*
+                    code => $name::Unknown(code),
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_i64(self.code())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                i64::deserialize(deserializer).map(Self::from_code)
+            }
+        }
+    };
+}
+
+coded_enum! {
+    pub enum Cmd {
+        Buy = 0,
+        Sell = 1,
+        BuyLimit = 2,
+        SellLimit = 3,
+        BuyStop = 4,
+        SellStop = 5,
+        Balance = 6,
+        Credit = 7,
+    }
+}
+
+coded_enum! {
+    pub enum r#Type {
+        Open = 0,
+        Pending = 1,
+        Close = 2,
+        Modify = 3,
+        Delete = 4,
+    }
+}
+
+/// Sent as `"Modified"`/`"Deleted"`, matched case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum State {
     Modified,
     Deleted,
+    Unknown(String),
+}
+
+impl Serialize for State {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            State::Modified => "Modified",
+            State::Deleted => "Deleted",
+            State::Unknown(raw) => raw,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for State {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        Ok(match raw.to_lowercase().as_str() {
+            "modified" => State::Modified,
+            "deleted" => State::Deleted,
+            _ => State::Unknown(raw),
+        })
+    }
 }
 
-#[derive(Debug, Deserialize_repr, Serialize_repr)]
-#[repr(u8)]
-pub enum RequestStatus {
-    Error = 0,
-    Pending = 1,
-    Accepted = 3,
-    Rejected = 4,
+coded_enum! {
+    pub enum RequestStatus {
+        Error = 0,
+        Pending = 1,
+        Accepted = 3,
+        Rejected = 4,
+    }
+}
+
+coded_enum! {
+    pub enum Period {
+        M1 = 1,
+        M5 = 5,
+        M15 = 15,
+        M30 = 30,
+        H1 = 60,
+        H4 = 240,
+        D1 = 1440,
+        W1 = 10080,
+        MN1 = 43200,
+    }
+}
+
+impl Period {
+    pub fn minutes(&self) -> i64 {
+        self.code()
+    }
+
+    pub fn millis(&self) -> i64 {
+        self.minutes() * 60 * 1000
+    }
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "M1" => Ok(Period::M1),
+            "M5" => Ok(Period::M5),
+            "M15" => Ok(Period::M15),
+            "M30" => Ok(Period::M30),
+            "H1" => Ok(Period::H1),
+            "H4" => Ok(Period::H4),
+            "D1" => Ok(Period::D1),
+            "W1" => Ok(Period::W1),
+            "MN1" => Ok(Period::MN1),
+            _ => Err(format!("Unknown period {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xapi_definitions::commands_stream::GetTradeStatusResponse;
+    use serde_json::json;
+
+    #[test]
+    fn codes_round_trip_including_unknown_ones() {
+        for code in [0, 3, 4, 7] {
+            let cmd: Cmd = serde_json::from_value(json!(code)).unwrap();
+            assert_eq!(serde_json::to_value(cmd).unwrap(), json!(code));
+        }
+        assert_eq!(serde_json::from_value::<Cmd>(json!(3)).unwrap(), Cmd::SellLimit);
+
+        let cmd: Cmd = serde_json::from_value(json!(42)).unwrap();
+        assert_eq!(cmd, Cmd::Unknown(42));
+        assert_eq!(serde_json::to_value(cmd).unwrap(), json!(42));
+
+        let status: RequestStatus = serde_json::from_value(json!(-1)).unwrap();
+        assert_eq!(status, RequestStatus::Unknown(-1));
+        assert_eq!(serde_json::to_value(status).unwrap(), json!(-1));
+        assert_eq!(Period::from_code(1440), Period::D1);
+    }
+
+    #[test]
+    fn state_ignores_case_and_keeps_unknown_values() {
+        assert_eq!(serde_json::from_value::<State>(json!("Modified")).unwrap(), State::Modified);
+        assert_eq!(serde_json::from_value::<State>(json!("DELETED")).unwrap(), State::Deleted);
+        assert_eq!(serde_json::from_value::<State>(json!("deleted")).unwrap(), State::Deleted);
+
+        let state: State = serde_json::from_value(json!("Suspended")).unwrap();
+        assert_eq!(state, State::Unknown("Suspended".into()));
+        assert_eq!(serde_json::to_value(&state).unwrap(), json!("Suspended"));
+        assert_eq!(serde_json::to_value(State::Deleted).unwrap(), json!("Deleted"));
+    }
+
+    #[test]
+    fn extra_keeps_unknown_fields_through_a_round_trip() {
+        let message = json!({
+            "customComment": null,
+            "message": null,
+            "order": 7,
+            "price": 1.5,
+            "requestStatus": 5,
+            "venue": {"name": "xstation", "latencyMs": 3},
+        });
+        let status: GetTradeStatusResponse = serde_json::from_value(message.clone()).unwrap();
+        assert_eq!(status.request_status, RequestStatus::Unknown(5));
+        assert_eq!(status.extra["venue"]["latencyMs"], json!(3));
+        assert_eq!(serde_json::to_value(&status).unwrap(), message);
+    }
 }
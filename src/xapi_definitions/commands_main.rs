@@ -1,15 +1,26 @@
-use super::commands_common::{Cmd, Type, RequestStatus};
+use super::commands_common::{Cmd, Type, RequestStatus, Period, Extra};
 
-use serde::{Deserialize, Serialize};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::from_value_traced;
+use crate::secret::Secret;
 
 pub trait ValidResponse {}
 
 impl ValidResponse for LoginResponse{}
 impl ValidResponse for GetCommissionDefResponse{}
 impl ValidResponse for GetCurrentUserDataResponse{}
+impl ValidResponse for GetMarginLevelResponse{}
+impl ValidResponse for GetMarginTradeResponse{}
 impl ValidResponse for ErrorResponse{}
 impl ValidResponse for TradeTransactionResponse{}
 impl ValidResponse for TradeTransactionStatusResponse{}
+impl ValidResponse for GetChartResponse{}
+impl ValidResponse for SymbolRecord{}
+impl ValidResponse for TradeRecord{}
+impl<T: ValidResponse> ValidResponse for Vec<T>{}
 
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -19,15 +30,23 @@ pub enum Request {
     Logout(LogoutRequest),
     GetMarginTrade(GetMarginTradeRequest),
     GetChartLast(GetChartLastRequest),
+    #[serde(rename = "getChartRangeRequest")]
+    GetChartRange(GetChartRangeRequest),
+    GetAllSymbols(GetAllSymbols),
     GetSymbol(GetSymbol),
     GetCommissionDef(GetCommissionDef),
     GetCurrentUserData(GetCurrentUserData),
+    GetMarginLevel(GetMarginLevel),
+    GetTrades(GetTradesRequest),
+    GetTradesHistory(GetTradesHistoryRequest),
     TradeTransaction(TradeTransaction),
     TradeTransactionStatus(TradeTransactionStatus),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
+/// Reply on the main connection. Decoding branches on `status` and on which of
+/// `streamSessionId`, `returnData` and `errorCode` is present, then deserializes
+/// that variant only, so errors name the field that failed.
+#[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum Response<T:ValidResponse + Serialize> {
     Login(LoginResponse),
@@ -35,35 +54,86 @@ pub enum Response<T:ValidResponse + Serialize> {
     Error(ErrorResponse),
 }
 
+impl<'de, T: ValidResponse + Serialize + Deserialize<'de>> Deserialize<'de> for Response<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let reply = Value::deserialize(deserializer)?;
+        let Some(fields) = reply.as_object() else {
+            return Err(D::Error::custom(format!("expected a JSON object, got {}", kind(&reply))));
+        };
+        let status = match fields.get("status") {
+            Some(Value::Bool(status)) => *status,
+            Some(other) => return Err(D::Error::custom(format!("status: expected a boolean, got {}", kind(other)))),
+            None => return Err(D::Error::missing_field("status")),
+        };
+
+        if !status {
+            if !fields.contains_key("errorCode") {
+                return Err(D::Error::custom("status is false but errorCode is missing"));
+            }
+            return from_value_traced(reply).map(Response::Error);
+        }
+        if fields.contains_key("streamSessionId") {
+            return from_value_traced(reply).map(Response::Login);
+        }
+        if fields.contains_key("returnData") {
+            return from_value_traced(reply).map(Response::Data);
+        }
+        Err(D::Error::custom("status is true but neither returnData nor streamSessionId is present"))
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetResponse <T: Serialize> {
     pub status: bool,
     pub return_data: T, 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_tag: Option<String>,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginRequest {
     pub user_id: String,
-    pub password: String,
+    pub password: Secret,
     pub app_id: String,
     pub app_name: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginResponse {
     pub status: bool,
-    pub stream_session_id: String,
+    pub stream_session_id: Secret,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_tag: Option<String>,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorResponse {
     pub status: bool,
     pub error_code: String,
     pub error_descr: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_tag: Option<String>,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -78,6 +148,13 @@ pub struct GetMarginTradeRequest {
     pub volume: f32,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetMarginTradeResponse {
+    pub margin: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetChartLastRequest {
     pub period: u32,
@@ -85,29 +162,100 @@ pub struct GetChartLastRequest {
     pub symbol: String,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetChartRangeRequest {
+    pub info: ChartRangeInfoRecord,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChartRangeInfoRecord {
+    pub end: i64,
+    pub period: Period,
+    pub start: i64,
+    pub symbol: String,
+    pub ticks: i32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetChartResponse {
+    pub digits: u32,
+    pub rate_infos: Vec<RateInfoRecord>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+/// `open` is the price multiplied by 10^digits, `close`, `high` and `low` are shifts from `open`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RateInfoRecord {
+    pub close: f64,
+    pub ctm: i64,
+    pub ctm_string: String,
+    pub high: f64,
+    pub low: f64,
+    pub open: f64,
+    pub vol: f64,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetAllSymbols {
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetSymbol {
     pub symbol: String,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolRecord {
+    pub ask: f32,
+    pub bid: f32,
+    pub category_name: String,
+    pub contract_size: i64,
+    pub currency: String,
+    pub currency_profit: String,
+    pub description: String,
+    pub digits: u16,
+    pub high: f32,
+    pub leverage: f32,
+    pub lot_max: f32,
+    pub lot_min: f32,
+    pub lot_step: f32,
+    pub low: f32,
+    pub precision: i32,
+    pub spread_raw: f32,
+    pub spread_table: f32,
+    pub symbol: String,
+    pub time: i64,
+    pub trailing_enabled: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetCommissionDef {
     pub symbol: String,
     pub volume: f32,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetCommissionDefResponse {
     pub commission: f32,
     pub rate_of_exchange: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GetCurrentUserData {
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetCurrentUserDataResponse {
     pub company_unit: i32,
@@ -118,6 +266,67 @@ pub struct GetCurrentUserDataResponse {
     pub leverage_multiplier: f32,
     pub spread_type: Option<String>,
     pub trailing_stop: bool,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetMarginLevel {
+}
+
+/// Unlike the balance stream, xAPI sends `margin_free` and `margin_level` in snake case here.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GetMarginLevelResponse {
+    pub balance: f32,
+    pub credit: f32,
+    pub currency: String,
+    pub equity: f32,
+    pub margin: f32,
+    pub margin_free: f32,
+    pub margin_level: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetTradesRequest {
+    pub opened_only: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GetTradesHistoryRequest {
+    pub end: i64,
+    pub start: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TradeRecord {
+   pub  close_price: f32,
+   pub  close_time: Option<i64>,
+   pub  closed: bool,
+   pub  cmd: Cmd,
+   pub  comment: Option<String>,
+   pub  commission: Option<f32>,
+   #[serde(rename = "customComment")]
+   pub  custom_comment: Option<String>,
+   pub  digits: u16,
+   pub  expiration: Option<i64>,
+   pub  margin_rate: f32,
+   pub  offset: u16,
+   pub  open_price: f32,
+   pub  open_time: i64,
+   pub  order: u32,
+   pub  order2: u32,
+   pub  position: u32,
+   pub  profit: Option<f32>,
+   pub  sl: f32,
+   pub  storage: f32,
+   pub  symbol: Option<String>,
+   pub  tp: f32,
+   pub  volume: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -126,7 +335,7 @@ pub struct TradeTransaction {
     pub trade_trans_info: TradeTransInfo,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TradeTransInfo {
    pub  cmd: Cmd,
    #[serde(rename = "customComment")]
@@ -142,9 +351,11 @@ pub struct TradeTransInfo {
    pub  volume: f32, 
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TradeTransactionResponse {
     pub order: u32,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -152,7 +363,7 @@ pub struct TradeTransactionStatus {
     pub order: u32,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TradeTransactionStatusResponse {
     pub ask: f32,
@@ -162,4 +373,66 @@ pub struct TradeTransactionStatusResponse {
     pub message: Option<String>,
     pub order: u32,
     pub request_status: RequestStatus,
-}
\ No newline at end of file
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn response(reply: Value) -> Result<Response<GetMarginTradeResponse>, String> {
+        serde_json::from_value(reply).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn false_status_decodes_the_error() {
+        let reply = json!({"status": false, "errorCode": "BE005", "errorDescr": "userPasswordCheck: Invalid login or password", "customTag": "3"});
+        match response(reply).unwrap() {
+            Response::Error(err) => {
+                assert_eq!(err.error_code, "BE005");
+                assert_eq!(err.custom_tag.as_deref(), Some("3"));
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+
+        let err = response(json!({"status": false, "errorDescr": "no code"})).unwrap_err();
+        assert_eq!(err, "status is false but errorCode is missing");
+    }
+
+    #[test]
+    fn stream_session_id_decodes_a_login() {
+        match response(json!({"status": true, "streamSessionId": "8469308861804289383"})).unwrap() {
+            Response::Login(login) => assert_eq!(login.stream_session_id.expose(), "8469308861804289383"),
+            other => panic!("expected a login, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn return_data_decodes_the_requested_type() {
+        match response(json!({"status": true, "returnData": {"margin": 4399.35}, "customTag": "7"})).unwrap() {
+            Response::Data(data) => {
+                assert_eq!(data.return_data.margin, 4399.35);
+                assert_eq!(data.custom_tag.as_deref(), Some("7"));
+            }
+            other => panic!("expected data, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_or_invalid_fields_are_named() {
+        let err = response(json!({"status": true})).unwrap_err();
+        assert_eq!(err, "status is true but neither returnData nor streamSessionId is present");
+
+        let err = response(json!({"returnData": {"margin": 1.0}})).unwrap_err();
+        assert_eq!(err, "missing field `status`");
+        let err = response(json!({"status": "true", "returnData": {"margin": 1.0}})).unwrap_err();
+        assert_eq!(err, "status: expected a boolean, got a string");
+        let err = response(json!([true])).unwrap_err();
+        assert_eq!(err, "expected a JSON object, got an array");
+
+        // Decoding errors of the chosen variant carry the path of the failing field.
+        let err = response(json!({"status": true, "returnData": {"margin": "high"}})).unwrap_err();
+        assert!(err.starts_with("returnData.margin: invalid type: string \"high\""), "{}", err);
+    }
+}
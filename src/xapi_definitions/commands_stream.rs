@@ -1,43 +1,105 @@
-use super::commands_common::{Cmd, Type, State, RequestStatus};
+use super::commands_common::{Cmd, Type, State, RequestStatus, Extra};
 
-use serde::{Deserialize, Serialize};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-#[derive(Debug, Deserialize, Serialize)]
+use super::from_value_traced;
+
+/// Subscriptions, sent without `streamSessionId` which the stream connection adds itself.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all="camelCase", tag = "command")]
 pub enum RequestStream {
     GetCandles(GetCandles),
     GetBalance(GetBalance),
     GetKeepAlive(GetKeepAlive),
+    GetProfits(GetProfits),
     GetTickPrices(GetTickPrices),
     GetTrades(GetTrades),
     GetTradeStatus(GetTradeStatus),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Streamed message. Commands this version does not know are kept as `Unknown`
+/// instead of failing, a known command with invalid data still fails.
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all="camelCase", tag = "command")]
 pub enum ResponseStream {
     Candle(GetResponse<GetCandlesResponse>),
     Balance(GetResponse<GetBalanceResponse>),
     KeepAlive(GetResponse<GetKeepAliveResponse>),
+    Profit(GetResponse<GetProfitsResponse>),
     TickPrices(GetResponse<GetTickPricesResponse>),
     Trade(GetResponse<GetTradesReponse>),
     TradeStatus(GetResponse<GetTradeStatusResponse>),
+    /// Whole message, `command` included.
+    #[serde(untagged)]
+    Unknown(Value),
+}
+
+impl<'de> Deserialize<'de> for ResponseStream {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut message = Value::deserialize(deserializer)?;
+        let command = match message.as_object_mut().and_then(|fields| fields.remove("command")) {
+            Some(Value::String(command)) => command,
+            _ => return Err(D::Error::missing_field("command")),
+        };
+        Ok(match command.as_str() {
+            "candle" => ResponseStream::Candle(from_value_traced(message)?),
+            "balance" => ResponseStream::Balance(from_value_traced(message)?),
+            "keepAlive" => ResponseStream::KeepAlive(from_value_traced(message)?),
+            "profit" => ResponseStream::Profit(from_value_traced(message)?),
+            "tickPrices" => ResponseStream::TickPrices(from_value_traced(message)?),
+            "trade" => ResponseStream::Trade(from_value_traced(message)?),
+            "tradeStatus" => ResponseStream::TradeStatus(from_value_traced(message)?),
+            _ => {
+                if let Some(fields) = message.as_object_mut() {
+                    fields.insert("command".into(), command.into());
+                }
+                ResponseStream::Unknown(message)
+            }
+        })
+    }
+}
+
+impl ResponseStream {
+    pub fn command(&self) -> &str {
+        match self {
+            ResponseStream::Candle(_) => "candle",
+            ResponseStream::Balance(_) => "balance",
+            ResponseStream::KeepAlive(_) => "keepAlive",
+            ResponseStream::Profit(_) => "profit",
+            ResponseStream::TickPrices(_) => "tickPrices",
+            ResponseStream::Trade(_) => "trade",
+            ResponseStream::TradeStatus(_) => "tradeStatus",
+            ResponseStream::Unknown(message) => message["command"].as_str().unwrap_or_default(),
+        }
+    }
+
+    pub fn symbol(&self) -> Option<&str> {
+        match self {
+            ResponseStream::Candle(candle) => Some(&candle.data.symbol),
+            ResponseStream::TickPrices(tick) => Some(&tick.data.symbol),
+            ResponseStream::Trade(trade) => Some(&trade.data.symbol),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetResponse <T: Serialize> {
     pub data: T, 
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetCandles {
-    pub stream_session_id: String,
 	pub symbol: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetCandlesResponse {
     pub close: f32,
@@ -49,15 +111,16 @@ pub struct GetCandlesResponse {
     pub quote_id: i32,
     pub symbol: String,
     pub vol: f32, 
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetBalance {
-    pub stream_session_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetBalanceResponse {
     pub balance: f32,
@@ -66,30 +129,48 @@ pub struct GetBalanceResponse {
     pub margin: f32,
     pub margin_free: f32,
     pub margin_level: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetKeepAlive {
-    pub stream_session_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetKeepAliveResponse {
     pub timestamp: i64,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetProfits {
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetProfitsResponse {
+    pub order: u32,
+    pub order2: u32,
+    pub position: u32,
+    pub profit: f32,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetTickPrices {
-    pub stream_session_id: String,
     pub symbol: String,
     pub min_arrival_time: Option<i32>,
     pub max_level: Option<i32>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetTickPricesResponse {
     pub ask: f32,
@@ -104,15 +185,16 @@ pub struct GetTickPricesResponse {
     pub spread_table: f32,
     pub symbol: String,
     pub timestamp: i64,
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetTrades {
-    pub stream_session_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GetTradesReponse {
    pub  close_price: f32,
    pub  close_time: Option<i64>,
@@ -139,15 +221,16 @@ pub struct GetTradesReponse {
    pub  tp: f32,
    pub  r#type: r#Type,
    pub  volume: f32, 
+    #[serde(flatten)]
+    pub extra: Extra,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetTradeStatus {
-    pub stream_session_id: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetTradeStatusResponse {
     #[serde(rename = "customComment")]
@@ -156,4 +239,48 @@ pub struct GetTradeStatusResponse {
     pub order: u32,
     pub price: f32,
     pub request_status: RequestStatus,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(message: Value) -> Result<ResponseStream, String> {
+        serde_json::from_value(message).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn command_selects_the_variant() {
+        match message(json!({"command": "keepAlive", "data": {"timestamp": 1_700_000_000_000i64}})).unwrap() {
+            ResponseStream::KeepAlive(keep_alive) => assert_eq!(keep_alive.data.timestamp, 1_700_000_000_000),
+            other => panic!("expected a keep-alive, got {:?}", other),
+        }
+        let profit = json!({"command": "profit", "data": {"order": 7, "order2": 8, "position": 7, "profit": -1.5}});
+        assert_eq!(message(profit).unwrap().command(), "profit");
+    }
+
+    #[test]
+    fn unknown_commands_keep_the_whole_message() {
+        let news = json!({"command": "news", "data": {"title": "Rates unchanged", "time": 1}});
+        let parsed = message(news.clone()).unwrap();
+        assert_eq!(parsed.command(), "news");
+        match &parsed {
+            ResponseStream::Unknown(value) => assert_eq!(value, &news),
+            other => panic!("expected an unknown message, got {:?}", other),
+        }
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), news);
+    }
+
+    #[test]
+    fn invalid_messages_fail_with_the_field_path() {
+        assert_eq!(message(json!({"data": {}})).unwrap_err(), "missing field `command`");
+        assert_eq!(message(json!({"command": 5, "data": {}})).unwrap_err(), "missing field `command`");
+
+        // A known command with invalid data fails rather than falling back to `Unknown`.
+        let err = message(json!({"command": "keepAlive", "data": {"timestamp": "now"}})).unwrap_err();
+        assert!(err.starts_with("data.timestamp: invalid type: string \"now\""), "{}", err);
+    }
 }
@@ -0,0 +1,91 @@
+mod common;
+
+use std::time::Duration;
+
+use xtb::accounts::AccountManager;
+use xtb::mock::{EngineConfig, MockConfig, MockHandle, PriceSource};
+use xtb::xapi_definitions::commands_common::{Cmd, Type};
+use xtb::xapi_definitions::commands_main::*;
+use xtb::xapi_definitions::commands_stream::ResponseStream;
+
+async fn mock(users: &[&str], currency: &str, balance: f64) -> MockHandle {
+    let mut config = MockConfig::default()
+        .engine(EngineConfig { balance, prices: PriceSource::Manual });
+    for user_id in users {
+        config = config.user(user_id, common::PASSWORD);
+    }
+    config.fixtures.current_user_data.currency = currency.into();
+    common::mock(config).await
+}
+
+fn login(mock: &MockHandle, user_id: &str) -> (xtb::config::ClientConfig, Request) {
+    let config = mock.client_config();
+    let login_request = config.login_request(user_id, common::PASSWORD);
+    (config, login_request)
+}
+
+async fn open(accounts: &mut AccountManager, account: &str, cmd: Cmd, volume: f32) {
+    let trade_trans_info = TradeTransInfo {
+        cmd,
+        custom_comment: None,
+        expiration: 0,
+        offset: 0,
+        order: 0,
+        price: 0.0,
+        sl: 0.0,
+        symbol: "EURUSD".into(),
+        tp: 0.0,
+        r#type: Type::Open,
+        volume,
+    };
+    let client = accounts.client(account).unwrap();
+    client.execute_command(&Request::TradeTransaction(TradeTransaction { trade_trans_info })).await.unwrap();
+    client.response_data::<TradeTransactionResponse>().await.unwrap();
+}
+
+/// Reads events until `account` has `count` positions.
+async fn wait_for_positions(accounts: &mut AccountManager, account: &str, count: usize) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while accounts.positions(account).len() < count {
+            if let Some(event) = accounts.next_event().await {
+                assert!(!matches!(event.event, ResponseStream::Unknown(_)), "{:?}", event);
+            }
+        }
+    }).await.unwrap();
+}
+
+#[tokio::test]
+async fn add_all_rejects_accounts_already_managed() {
+    let mock = mock(&["1234", "5678"], "EUR", 10_000.0).await;
+    let mut accounts = AccountManager::new();
+    accounts.add(mock.client_config(), login(&mock, "1234").1).await.unwrap();
+
+    let results = accounts.add_all(vec![login(&mock, "1234"), login(&mock, "5678"), login(&mock, "5678")]).await;
+    let mut results: Vec<(String, bool)> = results.into_iter().map(|(account, result)| (account, result.is_ok())).collect();
+    results.sort();
+    assert_eq!(results, [("1234".into(), false), ("5678".into(), false), ("5678".into(), true)]);
+    assert_eq!(accounts.accounts().collect::<Vec<_>>(), ["1234", "5678"]);
+}
+
+#[tokio::test]
+async fn balances_are_totalled_per_currency_and_volume_netted_across_accounts() {
+    let eur = mock(&["1234", "5678"], "EUR", 10_000.0).await;
+    let usd = mock(&["9000"], "USD", 5_000.0).await;
+    let mut accounts = AccountManager::new();
+    let results = accounts.add_all(vec![login(&eur, "1234"), login(&eur, "5678"), login(&usd, "9000")]).await;
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+
+    let totals = accounts.total_balance();
+    assert_eq!(totals["EUR"].accounts, 2);
+    assert_eq!(totals["EUR"].balance, 20_000.0);
+    assert_eq!(totals["USD"].accounts, 1);
+    assert_eq!(totals["USD"].balance, 5_000.0);
+
+    open(&mut accounts, "1234", Cmd::Buy, 0.3).await;
+    open(&mut accounts, "5678", Cmd::Sell, 0.1).await;
+    wait_for_positions(&mut accounts, "1234", 1).await;
+    wait_for_positions(&mut accounts, "5678", 1).await;
+
+    let net = accounts.net_volume();
+    assert!((net["EURUSD"] - 0.2).abs() < 1e-6, "{:?}", net);
+}
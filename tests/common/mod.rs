@@ -0,0 +1,30 @@
+//! Mock server fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use xtb::mock::{MockConfig, MockHandle, MockServer};
+use xtb::XApiClient;
+
+pub const USER: &str = "1234";
+pub const PASSWORD: &str = "secret";
+
+/// Mock accepting `USER` with `PASSWORD`.
+pub fn config() -> MockConfig {
+    MockConfig::default().user(USER, PASSWORD)
+}
+
+/// Starts `config` on free local ports.
+pub async fn mock(config: MockConfig) -> MockHandle {
+    MockServer::new(config).bind("127.0.0.1:0", "127.0.0.1:0").await.unwrap()
+}
+
+/// Logs in to `mock` as `user_id`.
+pub async fn login(mock: &MockHandle, user_id: &str) -> XApiClient {
+    let config = mock.client_config();
+    config.client().connect_options(mock.connect_options()).connect().await.unwrap()
+        .login(&config.login_request(user_id, PASSWORD)).await.unwrap()
+}
+
+/// Logs in to `mock` as `USER`.
+pub async fn client(mock: &MockHandle) -> XApiClient {
+    login(mock, USER).await
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use serde::{Deserialize, Serialize};
+
+use xtb::mock::{EngineConfig, Fixtures, MockHandle, PriceSource};
+use xtb::sim::Quote;
+use xtb::xapi_definitions::commands_common::{Cmd, Type};
+use xtb::xapi_definitions::commands_main::*;
+use xtb::XApiClient;
+
+const TIME: i64 = 1_700_000_000_000;
+
+fn fixtures() -> Fixtures {
+    let mut fixtures = Fixtures::default();
+    let mut index = fixtures.symbols[0].clone();
+    index.symbol = "US500".into();
+    index.bid = 5000.0;
+    index.ask = 5001.0;
+    index.contract_size = 50;
+    index.leverage = 5.0;
+    fixtures.symbols.push(index);
+    fixtures
+}
+
+async fn client() -> (MockHandle, XApiClient) {
+    let mut config = common::config()
+        .engine(EngineConfig { prices: PriceSource::Manual, ..EngineConfig::default() });
+    config.fixtures = fixtures();
+    let mock = common::mock(config).await;
+    let client = common::client(&mock).await;
+    (mock, client)
+}
+
+async fn query<T>(client: &mut XApiClient, request: Request) -> T
+where
+    T: ValidResponse + Serialize + for<'de> Deserialize<'de>,
+{
+    client.execute_command(&request).await.unwrap();
+    client.response_data::<T>().await.unwrap().return_data
+}
+
+async fn open(client: &mut XApiClient, symbol: &str, cmd: Cmd, volume: f32, sl: f32, tp: f32) -> u32 {
+    let trade_trans_info = TradeTransInfo {
+        cmd,
+        custom_comment: None,
+        expiration: 0,
+        offset: 0,
+        order: 0,
+        price: 0.0,
+        sl,
+        symbol: symbol.into(),
+        tp,
+        r#type: Type::Open,
+        volume,
+    };
+    let response: TradeTransactionResponse = query(client, Request::TradeTransaction(TradeTransaction { trade_trans_info })).await;
+    response.order
+}
+
+async fn open_trades(client: &mut XApiClient) -> Vec<TradeRecord> {
+    query(client, Request::GetTrades(GetTradesRequest { opened_only: true })).await
+}
+
+async fn closed_trades(client: &mut XApiClient) -> Vec<TradeRecord> {
+    query(client, Request::GetTradesHistory(GetTradesHistoryRequest { end: 0, start: 0 })).await
+}
+
+#[tokio::test]
+async fn margin_uses_the_contract_size_and_leverage_of_each_symbol() {
+    let (_mock, mut client) = client().await;
+    let margin = |symbol: &str| Request::GetMarginTrade(GetMarginTradeRequest { symbol: symbol.into(), volume: 1.0 });
+
+    let fx: GetMarginTradeResponse = query(&mut client, margin("EURUSD")).await;
+    assert!((fx.margin - 100_000.0 * 1.0852 * 3.33 / 100.0).abs() < 0.5, "{}", fx.margin);
+    let index: GetMarginTradeResponse = query(&mut client, margin("US500")).await;
+    assert!((index.margin - 50.0 * 5001.0 * 5.0 / 100.0).abs() < 0.5, "{}", index.margin);
+}
+
+#[tokio::test]
+async fn take_profit_closes_a_position_on_a_manual_quote() {
+    let (mock, mut client) = client().await;
+    let order = open(&mut client, "EURUSD", Cmd::Buy, 0.1, 1.0800, 1.0900).await;
+    let trades = open_trades(&mut client).await;
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].position, order);
+    assert_eq!(trades[0].open_price, 1.0852);
+
+    mock.quote(Quote { symbol: "EURUSD".into(), bid: 1.0880, ask: 1.0882, time: TIME });
+    assert_eq!(open_trades(&mut client).await.len(), 1);
+
+    mock.quote(Quote { symbol: "EURUSD".into(), bid: 1.0905, ask: 1.0907, time: TIME + 1000 });
+    assert!(open_trades(&mut client).await.is_empty());
+    let closed = closed_trades(&mut client).await;
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].position, order);
+    assert!((closed[0].profit.unwrap() - 53.0).abs() < 0.1, "{:?}", closed[0].profit);
+}
+
+#[tokio::test]
+async fn stop_loss_closes_a_position_with_the_symbol_contract_size() {
+    let (mock, mut client) = client().await;
+    let order = open(&mut client, "US500", Cmd::Sell, 1.0, 5010.0, 4900.0).await;
+    assert_eq!(open_trades(&mut client).await.len(), 1);
+
+    mock.quote(Quote { symbol: "US500".into(), bid: 5010.0, ask: 5011.0, time: TIME });
+    assert!(open_trades(&mut client).await.is_empty());
+    let closed = closed_trades(&mut client).await;
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].position, order);
+    assert_eq!(closed[0].profit, Some(-550.0));
+}
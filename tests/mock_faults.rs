@@ -0,0 +1,99 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use xtb::mock::{Channel, Fault, MockHandle, MAX_HOLD};
+use xtb::session::XApiSession;
+use xtb::xapi_definitions::commands_main::*;
+use xtb::xapi_definitions::commands_stream::ResponseStream;
+use xtb::{ConnectionClosed, XApiClient};
+
+async fn mock() -> MockHandle {
+    let mut config = common::config();
+    config.keep_alive = Some(Duration::from_millis(20));
+    common::mock(config).await
+}
+
+async fn session(mock: &MockHandle) -> XApiSession {
+    let config = mock.client_config();
+    let mut session = XApiSession::connect(&config, &config.login_request(common::USER, common::PASSWORD)).await.unwrap();
+    session.subscribe_keep_alive().await.unwrap();
+    session
+}
+
+async fn get_symbol(client: &mut XApiClient) -> Result<SymbolRecord, Box<dyn std::error::Error>> {
+    client.execute_command(&Request::GetSymbol(GetSymbol { symbol: "EURUSD".into() })).await?;
+    Ok(client.response_data::<SymbolRecord>().await?.return_data)
+}
+
+/// Starts coalescing stream frames and reads whatever was sent before it took effect.
+async fn hold_frames(mock: &MockHandle, session: &mut XApiSession) {
+    session.next_event().await.unwrap();
+    mock.inject(Channel::Stream, Fault::CoalesceFrames { frames: 1000 });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    while tokio::time::timeout(Duration::from_millis(10), session.next_event()).await.is_ok() {}
+}
+
+#[tokio::test]
+async fn split_frames_are_reassembled() {
+    let mock = mock().await;
+    mock.inject(Channel::Main, Fault::SplitFrames { chunk: 3, pause_ms: 1 });
+    mock.inject(Channel::Stream, Fault::SplitFrames { chunk: 5, pause_ms: 0 });
+    let mut session = session(&mock).await;
+
+    assert_eq!(get_symbol(session.client()).await.unwrap().symbol, "EURUSD");
+    for _ in 0..3 {
+        assert!(matches!(session.next_event().await.unwrap(), ResponseStream::KeepAlive(_)));
+    }
+}
+
+#[tokio::test]
+async fn dropped_main_connection_reports_connection_closed() {
+    let mock = mock().await;
+    let mut session = session(&mock).await;
+    mock.inject(Channel::Main, Fault::DropMidFrame { bytes: Some(10) });
+
+    let err = get_symbol(session.client()).await.unwrap_err();
+    assert!(err.is::<ConnectionClosed>(), "{}", err);
+}
+
+#[tokio::test]
+async fn dropped_stream_connection_discards_the_partial_frame() {
+    let mock = mock().await;
+    let mut session = session(&mock).await;
+    session.next_event().await.unwrap();
+    mock.inject(Channel::Stream, Fault::DropMidFrame { bytes: None });
+
+    let err = loop {
+        if let Err(err) = session.next_event().await {
+            break err;
+        }
+    };
+    assert!(err.is::<ConnectionClosed>(), "{}", err);
+
+    session.reconnect().await.unwrap();
+    assert!(matches!(session.next_event().await.unwrap(), ResponseStream::KeepAlive(_)));
+}
+
+#[tokio::test]
+async fn coalesced_frames_are_released_after_max_hold() {
+    let mock = mock().await;
+    let mut session = session(&mock).await;
+    hold_frames(&mock, &mut session).await;
+
+    let started = Instant::now();
+    tokio::time::timeout(MAX_HOLD * 4, session.next_event()).await.unwrap().unwrap();
+    assert!(started.elapsed() >= MAX_HOLD / 2, "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn coalesced_frames_are_released_when_the_fault_is_cleared() {
+    let mock = mock().await;
+    let mut session = session(&mock).await;
+    hold_frames(&mock, &mut session).await;
+
+    mock.clear_faults();
+    let started = Instant::now();
+    tokio::time::timeout(MAX_HOLD, session.next_event()).await.unwrap().unwrap();
+    assert!(started.elapsed() < MAX_HOLD / 2, "{:?}", started.elapsed());
+}
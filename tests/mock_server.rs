@@ -0,0 +1,56 @@
+mod common;
+
+use xtb::mock::{MockConfig, LOGIN_FAILED};
+use xtb::xapi_definitions::commands_main::*;
+
+#[tokio::test]
+async fn login_succeeds_and_answers_requests() {
+    let mock = common::mock(common::config()).await;
+    let mut client = common::client(&mock).await;
+
+    client.execute_command(&Request::GetSymbol(GetSymbol { symbol: "EURUSD".into() })).await.unwrap();
+    let symbol = client.response_data::<SymbolRecord>().await.unwrap().return_data;
+    assert_eq!(symbol.symbol, "EURUSD");
+    assert_eq!(symbol.contract_size, 100000);
+
+    client.connect_stream(&mock.stream_addr().port().to_string()).await.unwrap();
+}
+
+#[tokio::test]
+async fn login_fails_with_a_wrong_password() {
+    let mock = common::mock(common::config()).await;
+    let config = mock.client_config();
+    let client = config.client().connect().await.unwrap();
+    let err = client.login(&config.login_request(common::USER, "wrong")).await.err().unwrap();
+    assert!(err.to_string().contains(LOGIN_FAILED), "{}", err);
+
+    let client = config.client().connect().await.unwrap();
+    let err = client.login(&config.login_request("9999", common::PASSWORD)).await.err().unwrap();
+    assert!(err.to_string().contains(LOGIN_FAILED), "{}", err);
+}
+
+#[tokio::test]
+async fn login_over_tls_trusting_the_mock_certificate() {
+    let mock = common::mock(MockConfig { tls: true, ..common::config() }).await;
+    let config = mock.client_config();
+    assert!(!config.plaintext);
+    assert!(mock.certificate_pem().is_some());
+
+    // The self-signed certificate is not among the webpki roots.
+    assert!(config.client().connect().await.is_err());
+
+    let mut client = common::client(&mock).await;
+    client.execute_command(&Request::GetSymbol(GetSymbol { symbol: "EURUSD".into() })).await.unwrap();
+    assert_eq!(client.response_data::<SymbolRecord>().await.unwrap().return_data.symbol, "EURUSD");
+    client.connect_stream(&mock.stream_addr().port().to_string()).await.unwrap();
+}
+
+#[tokio::test]
+async fn connect_options_extended_from_the_profile_keep_plaintext() {
+    let mock = common::mock(common::config()).await;
+    let config = mock.client_config();
+    let options = config.connect_options().connect_timeout(std::time::Duration::from_secs(5));
+    assert!(options.is_plaintext());
+    config.client().connect_options(options).connect().await.unwrap()
+        .login(&config.login_request(common::USER, common::PASSWORD)).await.unwrap();
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::time::Duration;
+
+use pki_types::pem::PemObject;
+use pki_types::CertificateDer;
+use tokio::net::TcpListener;
+use xtb::mock::{MockConfig, MockHandle};
+use xtb::transport::{spki_sha256, ConnectOptions};
+
+async fn tls_mock() -> MockHandle {
+    common::mock(MockConfig { tls: true, ..common::config() }).await
+}
+
+async fn open(mock: &MockHandle, options: &ConnectOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let address = mock.main_addr();
+    options.open(&address.ip().to_string(), &address.port().to_string()).await.map(|_| ())
+}
+
+fn certificate(mock: &MockHandle) -> CertificateDer<'static> {
+    CertificateDer::from_pem_slice(mock.certificate_pem().unwrap().as_bytes()).unwrap()
+}
+
+#[tokio::test]
+async fn pinned_key_must_match_the_server_certificate() {
+    let mock = tls_mock().await;
+    let pin = spki_sha256(&certificate(&mock)).unwrap();
+
+    open(&mock, &mock.connect_options().pin_spki_sha256([0; 32]).pin_spki_sha256(pin)).await.unwrap();
+
+    let err = open(&mock, &mock.connect_options().pin_spki_sha256([0; 32])).await.unwrap_err();
+    assert!(err.to_string().contains("pinned"), "{}", err);
+}
+
+#[tokio::test]
+async fn pinning_does_not_replace_root_validation() {
+    let mock = tls_mock().await;
+    let pin = spki_sha256(&certificate(&mock)).unwrap();
+
+    assert!(open(&mock, &ConnectOptions::new().pin_spki_sha256(pin)).await.is_err());
+}
+
+#[tokio::test]
+async fn server_name_is_checked_against_the_certificate() {
+    let mock = tls_mock().await;
+
+    open(&mock, &mock.connect_options().server_name("localhost")).await.unwrap();
+    assert!(open(&mock, &mock.connect_options().server_name("xapi.internal")).await.is_err());
+}
+
+#[tokio::test]
+async fn tls_fails_against_a_plaintext_server() {
+    let mock = common::mock(common::config()).await;
+    let tls = ConnectOptions::new().handshake_timeout(Duration::from_secs(5));
+
+    assert!(open(&mock, &tls).await.is_err());
+    open(&mock, &tls.plaintext(true)).await.unwrap();
+}
+
+#[tokio::test]
+async fn handshake_times_out_when_the_server_stays_silent() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let _server = tokio::spawn(async move {
+        let (_socket, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    });
+
+    let options = ConnectOptions::new().handshake_timeout(Duration::from_millis(50));
+    let err = options.open(&address.ip().to_string(), &address.port().to_string()).await.err().unwrap();
+    assert!(err.to_string().contains("TLS handshake timed out"), "{}", err);
+}